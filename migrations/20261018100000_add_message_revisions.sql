@@ -0,0 +1,8 @@
+ALTER TABLE messages
+ADD COLUMN edited_at timestamp with time zone;
+CREATE TABLE message_revisions (
+    id uuid PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    message uuid NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    content text NOT NULL,
+    replaced_at timestamp with time zone NOT NULL DEFAULT current_timestamp
+)
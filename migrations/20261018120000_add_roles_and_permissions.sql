@@ -0,0 +1,28 @@
+CREATE TABLE roles (
+    id uuid PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    name text NOT NULL,
+    permissions bigint NOT NULL DEFAULT 0,
+    created_at timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
+-- the nil uuid is the implicit role every user has, it grants VIEW_CHANNEL and SEND_MESSAGES by default
+INSERT INTO roles (id, name, permissions)
+VALUES ('00000000-0000-0000-0000-000000000000', 'everyone', 3);
+CREATE TABLE role_members (
+    role uuid NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    member uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (role, member)
+);
+CREATE TABLE channel_role_overwrites (
+    channel uuid NOT NULL REFERENCES textchannels(id) ON DELETE CASCADE,
+    role uuid NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    allow bigint NOT NULL DEFAULT 0,
+    deny bigint NOT NULL DEFAULT 0,
+    PRIMARY KEY (channel, role)
+);
+CREATE TABLE channel_member_overwrites (
+    channel uuid NOT NULL REFERENCES textchannels(id) ON DELETE CASCADE,
+    member uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    allow bigint NOT NULL DEFAULT 0,
+    deny bigint NOT NULL DEFAULT 0,
+    PRIMARY KEY (channel, member)
+)
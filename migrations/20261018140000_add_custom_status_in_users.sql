@@ -0,0 +1,4 @@
+ALTER TABLE users
+ADD COLUMN status_text varchar(128),
+ADD COLUMN status_emoji varchar(32),
+ADD COLUMN status_expires_at timestamp with time zone
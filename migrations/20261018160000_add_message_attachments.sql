@@ -0,0 +1,26 @@
+CREATE TABLE attachments (
+    id uuid PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    message uuid NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    name text NOT NULL,
+    size bigint NOT NULL,
+    mime_type text NOT NULL,
+    width integer,
+    height integer,
+    hash char(64) NOT NULL,
+    position integer NOT NULL
+);
+CREATE INDEX attachments_message_idx ON attachments (message);
+-- messages are served with their attachments, this collects them as json so every message query can include them
+CREATE FUNCTION message_attachments(message_id uuid) RETURNS jsonb AS $$
+    SELECT COALESCE(jsonb_agg(jsonb_build_object(
+        'id', id,
+        'message', message,
+        'name', name,
+        'size', size,
+        'mime_type', mime_type,
+        'width', width,
+        'height', height,
+        'hash', hash
+    ) ORDER BY position), '[]'::jsonb)
+    FROM attachments WHERE message = message_id
+$$ LANGUAGE sql STABLE
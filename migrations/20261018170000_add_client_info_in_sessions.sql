@@ -0,0 +1,5 @@
+ALTER TABLE sessions
+ADD COLUMN last_used_at timestamp with time zone NOT NULL DEFAULT current_timestamp,
+ADD COLUMN user_agent text,
+ADD COLUMN ip text;
+CREATE INDEX sessions_subject_idx ON sessions (subject)
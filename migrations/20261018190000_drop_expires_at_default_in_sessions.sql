@@ -0,0 +1,3 @@
+-- the lifetime of sessions is configurable, so it is always set by azumaneo
+ALTER TABLE sessions
+ALTER COLUMN expires_at DROP DEFAULT
@@ -0,0 +1,8 @@
+ALTER TABLE textchannels
+ADD COLUMN direct boolean NOT NULL DEFAULT false;
+CREATE TABLE direct_members (
+    channel uuid NOT NULL REFERENCES textchannels(id) ON DELETE CASCADE,
+    member uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (channel, member)
+);
+CREATE INDEX direct_members_member_idx ON direct_members (member)
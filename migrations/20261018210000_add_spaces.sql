@@ -0,0 +1,44 @@
+CREATE TABLE spaces (
+    id uuid PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    name text NOT NULL,
+    description text,
+    owner uuid REFERENCES users(id) ON DELETE SET NULL,
+    public boolean NOT NULL DEFAULT false,
+    created_at timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
+-- existing channels, roles and users are moved into the default space, its id matches the id of the existing everyone role
+INSERT INTO spaces (id, name, public)
+VALUES ('00000000-0000-0000-0000-000000000000', 'azuma', true);
+CREATE TABLE space_members (
+    space uuid NOT NULL REFERENCES spaces(id) ON DELETE CASCADE,
+    member uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    joined_at timestamp with time zone NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (space, member)
+);
+CREATE INDEX space_members_member_idx ON space_members (member);
+INSERT INTO space_members (space, member)
+SELECT '00000000-0000-0000-0000-000000000000', id FROM users;
+CREATE TABLE space_invites (
+    space uuid NOT NULL REFERENCES spaces(id) ON DELETE CASCADE,
+    invitee uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    inviter uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at timestamp with time zone NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (space, invitee)
+);
+CREATE INDEX space_invites_invitee_idx ON space_invites (invitee);
+ALTER TABLE textchannels
+ADD COLUMN space uuid REFERENCES spaces(id) ON DELETE CASCADE;
+UPDATE textchannels SET space = '00000000-0000-0000-0000-000000000000' WHERE NOT direct;
+ALTER TABLE textchannels
+ADD CONSTRAINT textchannels_space_check CHECK (direct = (space IS NULL));
+CREATE INDEX textchannels_space_idx ON textchannels (space);
+ALTER TABLE roles
+ADD COLUMN space uuid REFERENCES spaces(id) ON DELETE CASCADE;
+UPDATE roles SET space = '00000000-0000-0000-0000-000000000000';
+ALTER TABLE roles
+ALTER COLUMN space SET NOT NULL;
+-- members of direct messages are resolved from direct_members now, the overwrites aren't needed anymore
+DELETE FROM channel_role_overwrites
+WHERE channel IN (SELECT id FROM textchannels WHERE direct);
+DELETE FROM channel_member_overwrites
+WHERE channel IN (SELECT id FROM textchannels WHERE direct)
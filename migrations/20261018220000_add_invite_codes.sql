@@ -0,0 +1,11 @@
+CREATE TABLE invite_codes (
+    code text PRIMARY KEY NOT NULL,
+    space uuid NOT NULL REFERENCES spaces(id) ON DELETE CASCADE,
+    channel uuid REFERENCES textchannels(id) ON DELETE CASCADE,
+    creator uuid REFERENCES users(id) ON DELETE SET NULL,
+    max_uses integer,
+    uses integer NOT NULL DEFAULT 0,
+    expires_at timestamp with time zone,
+    created_at timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
+CREATE INDEX invite_codes_space_idx ON invite_codes (space)
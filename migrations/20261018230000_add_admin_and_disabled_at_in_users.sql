@@ -0,0 +1,3 @@
+ALTER TABLE users
+ADD COLUMN admin boolean NOT NULL DEFAULT false,
+ADD COLUMN disabled_at timestamp with time zone
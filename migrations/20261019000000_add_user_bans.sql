@@ -0,0 +1,7 @@
+CREATE TABLE user_bans (
+    subject uuid PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reason text NOT NULL,
+    banned_by uuid REFERENCES users(id) ON DELETE SET NULL,
+    expires_at timestamp with time zone,
+    created_at timestamp with time zone NOT NULL DEFAULT current_timestamp
+)
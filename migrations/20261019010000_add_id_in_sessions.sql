@@ -0,0 +1,3 @@
+-- the token is the secret used to authenticate, sessions are referred to by this id when they're listed or revoked
+ALTER TABLE sessions
+ADD COLUMN id uuid NOT NULL UNIQUE DEFAULT gen_random_uuid()
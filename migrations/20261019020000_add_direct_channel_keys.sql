@@ -0,0 +1,7 @@
+-- the comma separated, sorted ids of the members of a direct message, so opening one doesn't have to compare the members of every direct message
+CREATE TABLE direct_channel_keys (
+    channel uuid PRIMARY KEY NOT NULL REFERENCES textchannels(id) ON DELETE CASCADE,
+    members text NOT NULL UNIQUE
+);
+INSERT INTO direct_channel_keys (channel, members)
+SELECT channel, string_agg(member::text, ',' ORDER BY member) FROM direct_members GROUP BY channel
@@ -2,21 +2,54 @@
 //! If you run into any problems, don't hesitate to create an issue on GitHub.
 //! Contributions are welcome, just take a look at currently open issues or create a new one.
 
+use std::env;
 use std::fs::read_to_string;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix::{Actor, Addr};
 use actix_web::middleware::Logger;
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
-use log::info;
+use log::{info, warn};
 use serde::Deserialize;
 use sqlx::{migrate, PgPool};
 
 use crate::models::error::AzumaError;
+use crate::models::gc::GarbageCollector;
+use crate::models::session::SessionLifetime;
 use crate::models::stateactor::StateActor;
+use crate::models::storage::{LocalStorage, Storage};
+use crate::models::user::User;
+use crate::routes::admin::{
+    ban_user, delete_channel, delete_space as admin_delete_space, disable_user, enable_user,
+    instance_stats, list_bans, list_users, revoke_user_sessions, unban_user,
+};
 use crate::routes::api::api_info;
+use crate::routes::attachment::fetch_attachment;
+use crate::routes::avatar::{fetch_avatar, remove_avatar, set_avatar};
+use crate::routes::directchannel::{list_direct_channels, open_direct_channel};
 use crate::routes::init_ws::init_ws;
-use crate::routes::message::send_msg;
-use crate::routes::textchannel::{create_textchannel, delete_textchannel};
+use crate::routes::invitecode::{
+    create_invite_code, fetch_invite_code, list_invite_codes, redeem_invite_code,
+    revoke_invite_code,
+};
+use crate::routes::message::{
+    delete_msg, edit_msg, get_messages, get_msg_revisions, send_msg, send_msg_with_attachments,
+};
+use crate::routes::role::{
+    add_role_member, create_role, delete_role, list_roles, remove_role_member, update_role,
+};
+use crate::routes::session::{list_sessions, logout, revoke_all_sessions, revoke_session};
+use crate::routes::space::{
+    create_space, delete_space, fetch_space, invite_to_space, join_space, leave_space,
+    list_space_invites, list_space_members, list_spaces, remove_space_member, update_space,
+};
+use crate::routes::textchannel::{
+    create_textchannel, delete_textchannel, fetch_textchannel, get_overwrites, list_textchannels,
+    remove_member_overwrite, remove_role_overwrite, set_member_overwrite, set_role_overwrite,
+    update_textchannel,
+};
 use crate::routes::user::{fetch_user, login_user, register_user, update_user};
 use crate::routes::userstatus::set_onlinestatus;
 use crate::websocket::broker::Broker;
@@ -32,9 +65,123 @@ async fn not_found(_req: HttpRequest) -> Result<HttpResponse, AzumaError> {
 
 /// The AzumaConfig is loaded on startup and made available in the Actix-Web data
 #[derive(Deserialize)]
-struct AzumaConfig {
-    host_uri: String,
-    db_uri: String,
+pub struct AzumaConfig {
+    pub host_uri: String,
+    pub db_uri: String,
+    /// Seconds between the pings the server sends to websocket clients
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    /// Seconds without any frame from a websocket client after which the connection is closed
+    #[serde(default = "default_client_timeout")]
+    pub client_timeout: u64,
+    /// Seconds a websocket client has to authenticate before the connection is closed
+    #[serde(default = "default_auth_timeout")]
+    pub auth_timeout: u64,
+    /// Seconds without activity on all connections of a user after which he/she is shown as `AFK`
+    #[serde(default = "default_afk_timeout")]
+    pub afk_timeout: u64,
+    /// Directory uploaded files are stored in
+    #[serde(default = "default_storage_path")]
+    pub storage_path: String,
+    /// Maximum size of an uploaded avatar in bytes
+    #[serde(default = "default_max_avatar_size")]
+    pub max_avatar_size: usize,
+    /// Maximum size of a single file attached to a message in bytes
+    #[serde(default = "default_max_attachment_size")]
+    pub max_attachment_size: usize,
+    /// Maximum amount of files attached to a single message
+    #[serde(default = "default_max_attachments")]
+    pub max_attachments: usize,
+    /// Mime types of files which may be attached to messages, `type/*` allows all subtypes
+    #[serde(default = "default_attachment_types")]
+    pub attachment_types: Vec<String>,
+    /// Seconds between the removals of expired data like sessions
+    #[serde(default = "default_gc_interval")]
+    pub gc_interval: u64,
+    /// Maximum amount of rows deleted by a single query of the garbage collection
+    #[serde(default = "default_gc_batch_size")]
+    pub gc_batch_size: i64,
+    /// Seconds a session stays valid without being used
+    #[serde(default = "default_session_idle_timeout")]
+    pub session_idle_timeout: i64,
+    /// Seconds after its creation a session expires, no matter how often it is used
+    #[serde(default = "default_session_max_lifetime")]
+    pub session_max_lifetime: i64,
+    /// Minimum seconds between two renewals of the same session
+    #[serde(default = "default_session_renewal_interval")]
+    pub session_renewal_interval: i64,
+    /// Only allow registering with an invite code
+    #[serde(default)]
+    pub require_invite: bool,
+    /// Names of the users who are made instance administrators on startup, users who don't exist yet are skipped
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+fn default_heartbeat_interval() -> u64 {
+    15
+}
+
+fn default_client_timeout() -> u64 {
+    45
+}
+
+fn default_auth_timeout() -> u64 {
+    10
+}
+
+fn default_afk_timeout() -> u64 {
+    300
+}
+
+fn default_storage_path() -> String {
+    "storage".to_owned()
+}
+
+fn default_max_avatar_size() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_max_attachment_size() -> usize {
+    25 * 1024 * 1024
+}
+
+fn default_max_attachments() -> usize {
+    10
+}
+
+fn default_attachment_types() -> Vec<String> {
+    [
+        "image/*",
+        "video/*",
+        "audio/*",
+        "text/plain",
+        "application/pdf",
+        "application/zip",
+    ]
+    .iter()
+    .map(|mime_type| mime_type.to_string())
+    .collect()
+}
+
+fn default_gc_interval() -> u64 {
+    3600
+}
+
+fn default_gc_batch_size() -> i64 {
+    1000
+}
+
+fn default_session_idle_timeout() -> i64 {
+    14 * 24 * 60 * 60
+}
+
+fn default_session_max_lifetime() -> i64 {
+    90 * 24 * 60 * 60
+}
+
+fn default_session_renewal_interval() -> i64 {
+    5 * 60
 }
 
 impl AzumaConfig {
@@ -42,20 +189,56 @@ impl AzumaConfig {
         let config_string = read_to_string(path).expect("couldn't load config from provided path");
         let config: AzumaConfig =
             toml::from_str(&config_string).expect("couldn't deserialize config");
+        // the garbage collection repeats its queries until one removes less than a batch, which never happens with 0
+        if config.gc_batch_size < 1 {
+            panic!("gc_batch_size has to be at least 1");
+        }
         config
     }
+
+    pub fn session_lifetime(&self) -> SessionLifetime {
+        SessionLifetime {
+            idle_timeout: chrono::Duration::seconds(self.session_idle_timeout),
+            max_lifetime: chrono::Duration::seconds(self.session_max_lifetime),
+            renewal_interval: chrono::Duration::seconds(self.session_renewal_interval),
+        }
+    }
+
+    /// Check a mime type against the configured `attachment_types`
+    pub fn allows_attachment_type(&self, mime_type: &str) -> bool {
+        let mime_type = mime_type.to_ascii_lowercase();
+        self.attachment_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(prefix) => mime_type.split('/').next() == Some(prefix),
+                None => allowed == &mime_type,
+            })
+    }
 }
 
 #[derive(Clone)]
 pub struct AzumaState {
+    pub config: Arc<AzumaConfig>,
     pub db: PgPool,
     pub broker: Addr<Broker>,
     pub state: Addr<StateActor>,
+    pub storage: Arc<dyn Storage>,
 }
 
 #[actix_web::main]
 async fn main() {
     pretty_env_logger::init_timed();
+    // `azumaneo grant-admin <name>` and `azumaneo revoke-admin <name>` change the instance administrators and exit
+    let args: Vec<String> = env::args().skip(1).collect();
+    let set_admin = match args.as_slice() {
+        [] => None,
+        [command, name] if command == "grant-admin" => Some((name.clone(), true)),
+        [command, name] if command == "revoke-admin" => Some((name.clone(), false)),
+        _ => {
+            eprintln!("usage: azumaneo [grant-admin <name> | revoke-admin <name>]");
+            process::exit(2);
+        }
+    };
     let config = AzumaConfig::load("config.toml");
 
     let db = PgPool::connect(&config.db_uri).await.unwrap();
@@ -64,18 +247,52 @@ async fn main() {
         .await
         .expect("couldn't run database migrations");
 
+    if let Some((name, admin)) = set_admin {
+        match User::set_admin_by_name(&name, admin, &db).await {
+            Ok(()) => {
+                info!(target: "Access Control", "Set instance administrator of user '{}' to {}", name, admin)
+            }
+            Err(AzumaError::NotFound) => {
+                eprintln!("there is no user named '{}'", name);
+                process::exit(1);
+            }
+            Err(err) => panic!("couldn't change the instance administrators: {:?}", err),
+        }
+        return;
+    }
+    for name in &config.admins {
+        match User::set_admin_by_name(name, true, &db).await {
+            Ok(()) => {}
+            Err(AzumaError::NotFound) => {
+                warn!(target: "Access Control", "Configured administrator '{}' doesn't exist, restart the server after he/she registered", name)
+            }
+            Err(err) => panic!("couldn't set up administrators: {:?}", err),
+        }
+    }
+
     let broker = Broker::new().start();
-    let state = StateActor::new().start();
+    GarbageCollector::new(
+        db.clone(),
+        Duration::from_secs(config.gc_interval),
+        config.gc_batch_size,
+    )
+    .start();
+    let state = StateActor::new(broker.clone(), Duration::from_secs(config.afk_timeout)).start();
 
+    let host_uri = config.host_uri.clone();
+    let max_avatar_size = config.max_avatar_size;
+    let storage = Arc::new(LocalStorage::new(&config.storage_path));
     let state = AzumaState {
+        config: Arc::new(config),
         db: db.clone(),
         broker: broker.clone(),
         state: state.clone(),
+        storage,
     };
 
     let server = HttpServer::new(move || {
         App::new()
-            .data(state.clone())
+            .app_data(web::Data::new(state.clone()))
             .wrap(Logger::default())
             // general API routes
             .route("/", web::get().to(api_info))
@@ -83,22 +300,116 @@ async fn main() {
             // user routes
             .route("/user/register", web::post().to(register_user))
             .route("/user/login", web::post().to(login_user))
+            .route("/user/logout", web::post().to(logout))
+            .route("/user/sessions", web::get().to(list_sessions))
+            .route("/user/sessions", web::delete().to(revoke_all_sessions))
+            .route("/user/sessions/{id}", web::delete().to(revoke_session))
             .route("/user/update", web::patch().to(update_user))
+            .service(
+                web::resource("/user/avatar")
+                    .app_data(web::PayloadConfig::new(max_avatar_size))
+                    .route(web::put().to(set_avatar))
+                    .route(web::delete().to(remove_avatar)),
+            )
             .route("/user/{user}", web::get().to(fetch_user))
             .route("/user/status/set", web::post().to(set_onlinestatus))
+            .route("/avatar/{hash}", web::get().to(fetch_avatar))
             // message routes
             .route("/message/send", web::post().to(send_msg))
+            .route(
+                "/message/send/attachments",
+                web::post().to(send_msg_with_attachments),
+            )
+            .route("/message/{id}", web::patch().to(edit_msg))
+            .route("/message/{id}", web::delete().to(delete_msg))
+            .route("/message/{id}/revisions", web::get().to(get_msg_revisions))
+            .route("/attachment/{id}", web::get().to(fetch_attachment))
+            // space routes
+            .route("/space", web::get().to(list_spaces))
+            .route("/space", web::post().to(create_space))
+            .route("/space/invites", web::get().to(list_space_invites))
+            .route("/space/{id}", web::get().to(fetch_space))
+            .route("/space/{id}", web::patch().to(update_space))
+            .route("/space/{id}", web::delete().to(delete_space))
+            .route("/space/{id}/join", web::post().to(join_space))
+            .route("/space/{id}/leave", web::post().to(leave_space))
+            .route("/space/{id}/members", web::get().to(list_space_members))
+            .route(
+                "/space/{id}/members/{user}",
+                web::delete().to(remove_space_member),
+            )
+            .route("/space/{id}/invites/{user}", web::put().to(invite_to_space))
+            .route("/space/{id}/channels", web::get().to(list_textchannels))
+            .route("/space/{id}/channels", web::post().to(create_textchannel))
+            .route("/space/{id}/roles", web::get().to(list_roles))
+            .route("/space/{id}/roles", web::post().to(create_role))
+            .route("/space/{id}/invite_codes", web::get().to(list_invite_codes))
+            .route(
+                "/space/{id}/invite_codes",
+                web::post().to(create_invite_code),
+            )
+            // invite code routes
+            .route("/invite/{code}", web::get().to(fetch_invite_code))
+            .route("/invite/{code}", web::post().to(redeem_invite_code))
+            .route("/invite/{code}", web::delete().to(revoke_invite_code))
             // textchannel stuff
-            .route("/channel", web::post().to(create_textchannel))
             .route("/channel", web::delete().to(delete_textchannel))
+            .route("/channel/{id}", web::get().to(fetch_textchannel))
+            .route("/channel/{id}", web::patch().to(update_textchannel))
+            .route("/channel/{id}/messages", web::get().to(get_messages))
+            .route("/channel/{id}/overwrites", web::get().to(get_overwrites))
+            .route(
+                "/channel/{id}/overwrites/roles/{role}",
+                web::put().to(set_role_overwrite),
+            )
+            .route(
+                "/channel/{id}/overwrites/roles/{role}",
+                web::delete().to(remove_role_overwrite),
+            )
+            .route(
+                "/channel/{id}/overwrites/members/{user}",
+                web::put().to(set_member_overwrite),
+            )
+            .route(
+                "/channel/{id}/overwrites/members/{user}",
+                web::delete().to(remove_member_overwrite),
+            )
+            // instance administration
+            .service(
+                web::scope("/admin")
+                    .route("/stats", web::get().to(instance_stats))
+                    .route("/users", web::get().to(list_users))
+                    .route("/users/{id}/disable", web::post().to(disable_user))
+                    .route("/users/{id}/enable", web::post().to(enable_user))
+                    .route(
+                        "/users/{id}/sessions",
+                        web::delete().to(revoke_user_sessions),
+                    )
+                    .route("/users/{id}/ban", web::put().to(ban_user))
+                    .route("/users/{id}/ban", web::delete().to(unban_user))
+                    .route("/bans", web::get().to(list_bans))
+                    .route("/channels/{id}", web::delete().to(delete_channel))
+                    .route("/spaces/{id}", web::delete().to(admin_delete_space)),
+            )
+            // direct message routes
+            .route("/dm", web::get().to(list_direct_channels))
+            .route("/dm", web::post().to(open_direct_channel))
+            // role routes
+            .route("/role/{id}", web::patch().to(update_role))
+            .route("/role/{id}", web::delete().to(delete_role))
+            .route("/role/{id}/members/{user}", web::put().to(add_role_member))
+            .route(
+                "/role/{id}/members/{user}",
+                web::delete().to(remove_role_member),
+            )
             // custom 404 response
             .default_service(web::route().to(not_found))
     });
 
-    info!("Starting azumaneo on {}", &config.host_uri);
+    info!("Starting azumaneo on {}", &host_uri);
     server
-        .bind(&config.host_uri)
-        .expect(&format!("couldn't bind to address {}", &config.host_uri))
+        .bind(&host_uri)
+        .unwrap_or_else(|_| panic!("couldn't bind to address {}", &host_uri))
         .run()
         .await
         .expect("couldn't run server");
@@ -0,0 +1,124 @@
+use std::io::Cursor;
+
+use image::io::Reader;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::storage::{content_hash, Storage};
+
+/// A file attached to a [`ChatMessage`](crate::models::message::ChatMessage)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Attachment {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub message: Uuid,
+    pub name: String,
+    /// Size of the file in bytes
+    pub size: i64,
+    /// The mime type the client declared when uploading the file
+    pub mime_type: String,
+    /// Width and height of images in pixels
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// The content address of the file in the [`Storage`]
+    #[serde(skip_serializing)]
+    pub hash: String,
+}
+
+/// An uploaded file which was stored, but isn't attached to a message yet
+pub struct AttachmentUpload {
+    pub name: String,
+    pub size: i64,
+    pub mime_type: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub hash: String,
+}
+
+impl Attachment {
+    /// Maximum length of a file name, longer names are cut off
+    const MAX_NAME_LENGTH: usize = 255;
+
+    /// Store an uploaded file and collect its metadata.
+    /// This hashes the file and reads image headers, so it shouldn't run on an async executor
+    pub fn store(
+        name: &str,
+        mime_type: &str,
+        data: &[u8],
+        storage: &dyn Storage,
+    ) -> Result<AttachmentUpload, AzumaError> {
+        let hash = content_hash(data);
+        storage.put(&Self::key(&hash), data)?;
+
+        // dimensions are only known for image formats we can decode, other files are attached without them
+        let dimensions = Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok());
+        let name = name.chars().take(Self::MAX_NAME_LENGTH).collect();
+
+        Ok(AttachmentUpload {
+            name,
+            size: data.len() as i64,
+            mime_type: mime_type.to_owned(),
+            width: dimensions.map(|(width, _)| width as i32),
+            height: dimensions.map(|(_, height)| height as i32),
+            hash,
+        })
+    }
+
+    /// Link stored uploads to a message, keeping their order
+    pub async fn attach(
+        message: &Uuid,
+        uploads: &[AttachmentUpload],
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<Self>, AzumaError> {
+        let mut attachments = Vec::with_capacity(uploads.len());
+        for (position, upload) in uploads.iter().enumerate() {
+            let attachment = query_as!(
+                Attachment,
+                "INSERT INTO attachments (message, name, size, mime_type, width, height, hash, position)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                RETURNING id, message, name, size, mime_type, width, height, hash",
+                message,
+                upload.name,
+                upload.size,
+                upload.mime_type,
+                upload.width,
+                upload.height,
+                upload.hash,
+                position as i32
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            attachments.push(attachment);
+        }
+
+        Ok(attachments)
+    }
+
+    pub async fn get_by_id(id: &Uuid, db: &PgPool) -> Result<Self, AzumaError> {
+        let attachment = query_as!(
+            Attachment,
+            "SELECT id, message, name, size, mime_type, width, height, hash FROM attachments WHERE id = $1",
+            id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        attachment.ok_or(AzumaError::NotFound)
+    }
+
+    /// Load the content of the file
+    pub fn load(&self, storage: &dyn Storage) -> Result<Vec<u8>, AzumaError> {
+        storage
+            .get(&Self::key(&self.hash))?
+            .ok_or(AzumaError::NotFound)
+    }
+
+    fn key(hash: &str) -> String {
+        format!("attachments/{}", hash)
+    }
+}
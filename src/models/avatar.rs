@@ -0,0 +1,73 @@
+use std::io::Cursor;
+
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::{ImageFormat, ImageOutputFormat};
+
+use crate::models::error::AzumaError;
+use crate::models::storage::{content_hash, is_content_hash, Storage};
+
+/// An uploaded avatar, stored as square PNG images in each of the [`Avatar::SIZES`]
+pub struct Avatar {
+    /// The hash of the largest size, which identifies the avatar
+    pub hash: String,
+}
+
+impl Avatar {
+    /// The edge lengths in pixels every avatar is available in, the last one is the default
+    pub const SIZES: [u32; 3] = [64, 128, 256];
+    /// Uploads with a larger width or height are rejected before they are decoded
+    const MAX_DIMENSION: u32 = 4096;
+
+    /// Validate an uploaded PNG, JPEG or WebP image and store it in all [`Avatar::SIZES`].
+    /// This is CPU heavy, so it shouldn't run on an async executor
+    pub fn store(data: &[u8], storage: &dyn Storage) -> Result<Self, AzumaError> {
+        let mut reader = Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .or(Err(AzumaError::BadRequest))?;
+        match reader.format() {
+            Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP) => (),
+            _ => return Err(AzumaError::BadRequest),
+        }
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(Self::MAX_DIMENSION);
+        limits.max_image_height = Some(Self::MAX_DIMENSION);
+        reader.limits(limits);
+        let image = reader.decode().or(Err(AzumaError::BadRequest))?;
+
+        let mut renditions = Vec::with_capacity(Self::SIZES.len());
+        for size in Self::SIZES {
+            let mut encoded = Vec::new();
+            image
+                .resize_to_fill(size, size, FilterType::Lanczos3)
+                .write_to(&mut Cursor::new(&mut encoded), ImageOutputFormat::Png)
+                .map_err(|err| AzumaError::InternalServerError {
+                    source: Box::new(err),
+                })?;
+            renditions.push((size, encoded));
+        }
+
+        let (_, largest) = renditions.last().expect("there is always an avatar size");
+        let hash = content_hash(largest);
+        for (size, encoded) in &renditions {
+            storage.put(&Self::key(&hash, *size), encoded)?;
+        }
+
+        Ok(Avatar { hash })
+    }
+
+    /// Load the PNG image of an avatar in one of the [`Avatar::SIZES`]
+    pub fn load(hash: &str, size: u32, storage: &dyn Storage) -> Result<Vec<u8>, AzumaError> {
+        if !is_content_hash(hash) || !Self::SIZES.contains(&size) {
+            return Err(AzumaError::BadRequest);
+        }
+
+        storage
+            .get(&Self::key(hash, size))?
+            .ok_or(AzumaError::NotFound)
+    }
+
+    fn key(hash: &str, size: u32) -> String {
+        format!("avatars/{}/{}.png", hash, size)
+    }
+}
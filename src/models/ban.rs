@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{query, query_as, PgPool};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+
+/// A banned user can neither log in nor use their existing sessions until the ban is lifted or expires.
+/// Bans with an expiry are suspensions, the user's sessions work again afterwards
+#[derive(Clone, Debug, Serialize)]
+pub struct Ban {
+    pub subject: Uuid,
+    pub reason: String,
+    /// The administrator who banned the user
+    pub banned_by: Option<Uuid>,
+    /// `None` bans the user permanently
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Ban {
+    /// Ban the user, banning them again replaces their previous ban
+    pub async fn new(
+        subject: &Uuid,
+        reason: &str,
+        banned_by: &Uuid,
+        expires_at: Option<DateTime<Utc>>,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let ban = query_as!(
+            Ban,
+            "INSERT INTO user_bans (subject, reason, banned_by, expires_at) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (subject) DO UPDATE SET reason = EXCLUDED.reason, banned_by = EXCLUDED.banned_by, expires_at = EXCLUDED.expires_at, created_at = current_timestamp
+            RETURNING *",
+            subject,
+            reason,
+            banned_by,
+            expires_at
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(ban)
+    }
+
+    /// Get the ban of the user unless they aren't banned or it expired
+    pub async fn get_active(subject: &Uuid, db: &PgPool) -> Result<Option<Self>, AzumaError> {
+        let ban = query_as!(
+            Ban,
+            "SELECT * FROM user_bans WHERE subject = $1 AND (expires_at IS NULL OR expires_at > current_timestamp)",
+            subject
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(ban)
+    }
+
+    /// Get all bans which didn't expire yet, newest first
+    pub async fn get_all_active(db: &PgPool) -> Result<Vec<Self>, AzumaError> {
+        let bans = query_as!(
+            Ban,
+            "SELECT * FROM user_bans WHERE expires_at IS NULL OR expires_at > current_timestamp ORDER BY created_at DESC"
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(bans)
+    }
+
+    /// Lift the ban of the user, fails with [`AzumaError::NotFound`] if they aren't banned
+    pub async fn remove(subject: &Uuid, db: &PgPool) -> Result<(), AzumaError> {
+        let result = query!(
+            "DELETE FROM user_bans WHERE subject = $1 AND (expires_at IS NULL OR expires_at > current_timestamp)",
+            subject
+        )
+        .execute(db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AzumaError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Delete up to `limit` expired bans, returns how many were deleted
+    pub async fn remove_expired(limit: i64, db: &PgPool) -> Result<u64, AzumaError> {
+        let result = query!(
+            "DELETE FROM user_bans WHERE subject IN (SELECT subject FROM user_bans WHERE expires_at <= current_timestamp LIMIT $1)",
+            limit
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,139 @@
+use actix::Addr;
+use serde::Serialize;
+use sqlx::{query, query_as, query_scalar, PgPool};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::textchannel::TextChannel;
+use crate::websocket::broker::{Broadcast, Broker};
+
+/// Upper bound for the amount of users in a direct message, including the one who opened it
+pub const MAX_MEMBERS: usize = 10;
+
+/// A private [`TextChannel`] between two or a small group of users, which doesn't belong to any space.
+/// Only its members can view it and send messages, see [`UserPermissions::in_channel`](crate::models::permission::UserPermissions::in_channel)
+#[derive(Clone, Debug, Serialize)]
+pub struct DirectChannel {
+    #[serde(flatten)]
+    pub channel: TextChannel,
+    /// Ordered by id, so the same group of users always results in the same list
+    pub members: Vec<Uuid>,
+}
+
+impl DirectChannel {
+    /// Get the direct message between exactly these users or create it if there is none yet.
+    /// The returned flag tells whether the channel was created
+    pub async fn open(
+        members: &[Uuid],
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(Self, bool), AzumaError> {
+        let mut members = members.to_vec();
+        members.sort();
+        members.dedup();
+        if members.len() < 2 || members.len() > MAX_MEMBERS {
+            return Err(AzumaError::BadRequest);
+        }
+
+        let key = Self::key(&members);
+        let existing = query_scalar!(
+            "SELECT channel FROM direct_channel_keys WHERE members = $1",
+            key
+        )
+        .fetch_optional(db)
+        .await?;
+        if let Some(channel) = existing {
+            return Ok((Self::get_by_id(&channel, db).await?, false));
+        }
+
+        let mut tx = db.begin().await?;
+        let channel = query_as!(
+            TextChannel,
+            "INSERT INTO textchannels (name, direct) VALUES ('', true) RETURNING *"
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        // the unique key makes a concurrent request for the same members wait for this one and back off
+        let inserted = query!(
+            "INSERT INTO direct_channel_keys (channel, members) VALUES ($1, $2) ON CONFLICT (members) DO NOTHING",
+            channel.id,
+            key
+        )
+        .execute(&mut tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            tx.rollback().await?;
+            let existing = query_scalar!(
+                "SELECT channel FROM direct_channel_keys WHERE members = $1",
+                key
+            )
+            .fetch_one(db)
+            .await?;
+            return Ok((Self::get_by_id(&existing, db).await?, false));
+        }
+        query!(
+            "INSERT INTO direct_members (channel, member) SELECT $1, unnest($2::uuid[])",
+            channel.id,
+            &members
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        let direct_channel = DirectChannel { channel, members };
+        broker.do_send(Broadcast::DirectChannelCreated(direct_channel.clone()));
+        Ok((direct_channel, true))
+    }
+
+    /// Identifies the direct message of a group of users, `members` has to be sorted
+    fn key(members: &[Uuid]) -> String {
+        members
+            .iter()
+            .map(Uuid::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub async fn get_by_id(id: &Uuid, db: &PgPool) -> Result<Self, AzumaError> {
+        let channel = TextChannel::get_by_id(db, id).await?;
+        if !channel.direct {
+            return Err(AzumaError::NotFound);
+        }
+        let members = query_scalar!(
+            "SELECT member FROM direct_members WHERE channel = $1 ORDER BY member",
+            id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(DirectChannel { channel, members })
+    }
+
+    /// Get all direct messages the user is a member of, newest first
+    pub async fn get_by_member(member: &Uuid, db: &PgPool) -> Result<Vec<Self>, AzumaError> {
+        let rows = query!(
+            r#"SELECT textchannels.*, ARRAY(SELECT member FROM direct_members WHERE channel = textchannels.id ORDER BY member) AS "members!"
+            FROM textchannels JOIN direct_members ON direct_members.channel = textchannels.id
+            WHERE direct_members.member = $1 ORDER BY textchannels.created_at DESC"#,
+            member
+        )
+        .fetch_all(db)
+        .await?;
+
+        let direct_channels = rows
+            .into_iter()
+            .map(|row| DirectChannel {
+                channel: TextChannel {
+                    id: row.id,
+                    name: row.name,
+                    description: row.description,
+                    created_at: row.created_at,
+                    direct: row.direct,
+                    space: row.space,
+                },
+                members: row.members,
+            })
+            .collect();
+        Ok(direct_channels)
+    }
+}
@@ -1,6 +1,7 @@
 use std::error::Error as ErrorTrait;
 
 use actix::{MailboxError, Message};
+use actix_web::error::BlockingError;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
 use serde::Serialize;
@@ -90,6 +91,22 @@ impl From<SqlxError> for AzumaError {
     }
 }
 
+impl From<std::io::Error> for AzumaError {
+    fn from(err: std::io::Error) -> Self {
+        AzumaError::InternalServerError {
+            source: Box::new(err),
+        }
+    }
+}
+
+impl From<BlockingError> for AzumaError {
+    fn from(err: BlockingError) -> Self {
+        AzumaError::InternalServerError {
+            source: Box::new(err),
+        }
+    }
+}
+
 impl From<uuid::Error> for AzumaError {
     fn from(_: uuid::Error) -> Self {
         Self::BadRequest
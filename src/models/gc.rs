@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use actix::{Actor, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, WrapFuture};
+use log::{debug, error, info};
+use sqlx::PgPool;
+
+use crate::models::ban::Ban;
+use crate::models::error::AzumaError;
+use crate::models::invitecode::InviteCode;
+use crate::models::session::Session;
+use crate::models::user::User;
+
+/// `GarbageCollector` periodically removes data from the database which expired and is never used again
+pub struct GarbageCollector {
+    db: PgPool,
+    interval: Duration,
+    /// Maximum amount of rows removed by a single query, so a large backlog doesn't lock a table for long
+    batch_size: i64,
+    /// Set while a collection runs, so a slow collection isn't started a second time
+    running: bool,
+}
+
+/// How much data a single collection removed
+#[derive(Default)]
+struct Collected {
+    sessions: u64,
+    custom_statuses: u64,
+    invite_codes: u64,
+    bans: u64,
+}
+
+impl GarbageCollector {
+    pub fn new(db: PgPool, interval: Duration, batch_size: i64) -> Self {
+        GarbageCollector {
+            db,
+            interval,
+            batch_size,
+            running: false,
+        }
+    }
+
+    async fn collect(db: PgPool, batch_size: i64) -> Result<Collected, AzumaError> {
+        let mut collected = Collected::default();
+        loop {
+            let removed = Session::remove_expired(batch_size, &db).await?;
+            collected.sessions += removed;
+            if removed < batch_size as u64 {
+                break;
+            }
+        }
+        loop {
+            let removed = User::clear_expired_custom_statuses(batch_size, &db).await?;
+            collected.custom_statuses += removed;
+            if removed < batch_size as u64 {
+                break;
+            }
+        }
+        loop {
+            let removed = InviteCode::remove_expired(batch_size, &db).await?;
+            collected.invite_codes += removed;
+            if removed < batch_size as u64 {
+                break;
+            }
+        }
+        loop {
+            let removed = Ban::remove_expired(batch_size, &db).await?;
+            collected.bans += removed;
+            if removed < batch_size as u64 {
+                break;
+            }
+        }
+
+        Ok(collected)
+    }
+}
+
+impl Actor for GarbageCollector {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, ctx| {
+            if act.running {
+                return;
+            }
+            act.running = true;
+
+            Self::collect(act.db.clone(), act.batch_size)
+                .into_actor(act)
+                .map(|result, act, _ctx| {
+                    act.running = false;
+                    match result {
+                        Ok(Collected {
+                            sessions: 0,
+                            custom_statuses: 0,
+                            invite_codes: 0,
+                            bans: 0,
+                        }) => debug!(target: "Garbage Collection", "Nothing expired"),
+                        Ok(collected) => info!(target: "Garbage Collection", "Removed {} expired sessions, {} expired custom statuses, {} expired invite codes and {} expired bans", collected.sessions, collected.custom_statuses, collected.invite_codes, collected.bans),
+                        Err(err) => error!(target: "Garbage Collection", "Collection failed: {:?}", err),
+                    }
+                })
+                .spawn(ctx);
+        });
+    }
+}
@@ -0,0 +1,192 @@
+use actix::Addr;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+use sqlx::{query, query_as, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::permission::{Permissions, UserPermissions};
+use crate::models::space::Space;
+use crate::websocket::broker::{Broker, SubSpace};
+
+/// Length of generated codes, only letters and digits are used so they can be put into urls as they are
+pub const CODE_LENGTH: usize = 10;
+
+/// A code which lets users join a space, or get access to a single channel of it.
+/// Unlike a [`SpaceInvite`](crate::models::space::SpaceInvite) it isn't bound to a user, so it can be shared as a link
+#[derive(Clone, Debug, Serialize)]
+pub struct InviteCode {
+    pub code: String,
+    pub space: Uuid,
+    /// Set if the code grants access to this channel in addition to joining the space
+    pub channel: Option<Uuid>,
+    pub creator: Option<Uuid>,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl InviteCode {
+    pub async fn new(
+        space: &Uuid,
+        channel: Option<&Uuid>,
+        creator: &Uuid,
+        max_uses: Option<i32>,
+        expires_at: Option<DateTime<Utc>>,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let code: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(CODE_LENGTH)
+            .map(char::from)
+            .collect();
+        let invite_code = query_as!(
+            InviteCode,
+            "INSERT INTO invite_codes (code, space, channel, creator, max_uses, expires_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+            code,
+            space,
+            channel,
+            creator,
+            max_uses,
+            expires_at
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(invite_code)
+    }
+
+    pub async fn get_by_code(code: &str, db: &PgPool) -> Result<Self, AzumaError> {
+        let invite_code = query_as!(
+            InviteCode,
+            "SELECT * FROM invite_codes WHERE code = $1",
+            code
+        )
+        .fetch_optional(db)
+        .await?;
+
+        invite_code.ok_or(AzumaError::NotFound)
+    }
+
+    /// Whether the code didn't expire and isn't used up yet
+    pub fn is_usable(&self) -> bool {
+        self.max_uses.is_none_or(|max_uses| self.uses < max_uses)
+            && self
+                .expires_at
+                .is_none_or(|expires_at| expires_at > Utc::now())
+    }
+
+    /// Get all codes of a space, including the ones which can't be used anymore but weren't removed yet
+    pub async fn get_by_space(space: &Uuid, db: &PgPool) -> Result<Vec<Self>, AzumaError> {
+        let invite_codes = query_as!(
+            InviteCode,
+            "SELECT * FROM invite_codes WHERE space = $1 ORDER BY created_at DESC",
+            space
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(invite_codes)
+    }
+
+    pub async fn revoke(code: &str, db: &PgPool) -> Result<(), AzumaError> {
+        let result = query!("DELETE FROM invite_codes WHERE code = $1", code)
+            .execute(db)
+            .await?;
+
+        match result.rows_affected() {
+            0 => Err(AzumaError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// The permissions a code for a single channel grants in that channel
+    pub fn channel_permissions() -> Permissions {
+        Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES
+    }
+
+    /// Count a use of the code, fails with [`AzumaError::NotFound`] if it expired or is used up in the meantime
+    pub async fn claim(code: &str, tx: &mut Transaction<'_, Postgres>) -> Result<Self, AzumaError> {
+        let invite_code = query_as!(
+            InviteCode,
+            "UPDATE invite_codes SET uses = uses + 1 WHERE code = $1
+            AND (max_uses IS NULL OR uses < max_uses) AND (expires_at IS NULL OR expires_at > current_timestamp)
+            RETURNING *",
+            code
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        invite_code.ok_or(AzumaError::NotFound)
+    }
+
+    /// Grant the user what the code was created for, in the transaction the code was [`claim`](Self::claim)ed in.
+    /// Users who are a member of the space already only get access to the channel.
+    /// Returns whether the user joined the space, [`announce_grant`](Self::announce_grant) needs it once the transaction is committed
+    pub async fn grant(
+        &self,
+        user: &Uuid,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, AzumaError> {
+        let joined = Space::insert_member(&self.space, user, tx).await?;
+
+        if let Some(channel) = &self.channel {
+            // keep other overwrites of the user in the channel, what they were explicitly denied stays denied
+            query!(
+                "INSERT INTO channel_member_overwrites (channel, member, allow) VALUES ($1, $2, $3)
+                ON CONFLICT (channel, member) DO UPDATE
+                SET allow = channel_member_overwrites.allow | (EXCLUDED.allow & ~channel_member_overwrites.deny)",
+                channel,
+                user,
+                Self::channel_permissions().bits()
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(joined)
+    }
+
+    /// Subscribe the connections of the user to what the code granted them
+    pub async fn announce_grant(
+        &self,
+        user: &Uuid,
+        joined: bool,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        if joined {
+            // this includes the channel of the code, as the user may view it now
+            let space = Space::get_by_id(&self.space, db).await?;
+            space.announce_member(user, broker, db).await?;
+        } else if let Some(channel) = &self.channel {
+            // a denial of the user may still keep them from viewing the channel
+            let permissions = UserPermissions::load_for_channel(user, channel, db).await?;
+            if permissions
+                .in_channel(channel)
+                .contains(Permissions::VIEW_CHANNEL)
+            {
+                broker.do_send(SubSpace {
+                    user: *user,
+                    space: self.space,
+                    channels: vec![*channel],
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete up to `limit` codes which expired or are used up, returns how many were deleted
+    pub async fn remove_expired(limit: i64, db: &PgPool) -> Result<u64, AzumaError> {
+        let result = query!(
+            "DELETE FROM invite_codes WHERE code IN (SELECT code FROM invite_codes WHERE expires_at <= current_timestamp OR uses >= max_uses LIMIT $1)",
+            limit
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -1,12 +1,19 @@
 use actix::prelude::*;
 use chrono::{DateTime, Utc};
 use serde::Serialize;
-use sqlx::{query_as, PgPool};
+use sqlx::types::Json;
+use sqlx::{query, query_as, query_scalar, PgPool};
 use uuid::Uuid;
 
+use crate::models::attachment::{Attachment, AttachmentUpload};
 use crate::models::error::AzumaError;
 use crate::websocket::broker::{Broadcast, Broker};
 
+/// Amount of messages returned by a history request if the client doesn't specify a limit
+pub const DEFAULT_HISTORY_LIMIT: i64 = 50;
+/// Upper bound for the amount of messages returned by a single history request
+pub const MAX_HISTORY_LIMIT: i64 = 100;
+
 /// This represents a chat message a user sends to a given channel
 #[derive(Clone, Message, Serialize)]
 #[rtype(response = "()")]
@@ -16,6 +23,44 @@ pub struct ChatMessage {
     pub channel: Uuid,
     pub content: String,
     pub created_at: DateTime<Utc>,
+    /// Set when the content of the message was edited, the prior contents are stored as [`MessageRevision`]s
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Set when the message was deleted. Deleted messages are only ever served as tombstones without content
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Files attached to the message, in the order they were uploaded
+    pub attachments: Json<Vec<Attachment>>,
+}
+
+/// A prior content of an edited [`ChatMessage`]
+#[derive(Clone, Serialize)]
+pub struct MessageRevision {
+    pub id: Uuid,
+    pub message: Uuid,
+    pub content: String,
+    /// The point in time this content was replaced by an edit
+    pub replaced_at: DateTime<Utc>,
+}
+
+/// Selects which part of a channels message history should be fetched
+#[derive(Clone, Copy, Debug)]
+pub enum HistoryCursor {
+    /// The newest messages of the channel
+    Latest,
+    /// The messages sent right before the given message
+    Before(Uuid),
+    /// The messages sent right after the given message
+    After(Uuid),
+    /// The given message and the messages surrounding it
+    Around(Uuid),
+}
+
+/// A page of a channels message history, ordered from newest to oldest.
+/// Deleted messages are included as tombstones, so clients can tell them apart from gaps in the history
+#[derive(Serialize)]
+pub struct MessageHistory {
+    pub messages: Vec<ChatMessage>,
+    /// Whether there are more messages in the paging direction which didn't fit in this page
+    pub has_more: bool,
 }
 
 impl ChatMessage {
@@ -26,30 +71,344 @@ impl ChatMessage {
         broker: &Addr<Broker>,
         db: &PgPool,
     ) -> Result<Self, AzumaError> {
-        let chat_message = query_as!(
+        Self::new_with_attachments(author, channel, content, &[], broker, db).await
+    }
+
+    /// Create a message with files which were already stored through [`Attachment::store`]
+    pub async fn new_with_attachments(
+        author: &Uuid,
+        channel: &Uuid,
+        content: &str,
+        uploads: &[AttachmentUpload],
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let mut tx = db.begin().await?;
+        let mut chat_message = query_as!(
             ChatMessage,
-            "INSERT INTO messages (author, channel, content) VALUES ($1, $2, $3) RETURNING *",
+            r#"INSERT INTO messages (author, channel, content) VALUES ($1, $2, $3) RETURNING *, message_attachments(id) AS "attachments!: Json<Vec<Attachment>>""#,
             author,
             channel,
             content
         )
-        .fetch_one(db)
+        .fetch_one(&mut tx)
         .await?;
+        let attachments = Attachment::attach(&chat_message.id, uploads, &mut tx).await?;
+        chat_message.attachments = Json(attachments);
+        tx.commit().await?;
 
         broker.do_send(Broadcast::ChatMessage(chat_message.clone()));
         Ok(chat_message)
     }
 
+    /// Get a message by its id
+    pub async fn get_by_id(id: &Uuid, db: &PgPool) -> Result<Self, AzumaError> {
+        let chat_message = query_as!(ChatMessage, r#"SELECT *, message_attachments(id) AS "attachments!: Json<Vec<Attachment>>" FROM messages WHERE id = $1"#, id)
+            .fetch_optional(db)
+            .await?;
+
+        chat_message.ok_or(AzumaError::NotFound)
+    }
+
+    /// Replace the content of the message, keeping the prior content as a [`MessageRevision`]
+    pub async fn edit(
+        &mut self,
+        content: &str,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let mut tx = db.begin().await?;
+        // the row lock keeps a concurrent edit or delete from changing the message between the snapshot and the update
+        let prior_content = query_scalar!(
+            "SELECT content FROM messages WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            self.id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AzumaError::NotFound)?;
+        query!(
+            "INSERT INTO message_revisions (message, content) VALUES ($1, $2)",
+            self.id,
+            prior_content
+        )
+        .execute(&mut tx)
+        .await?;
+        let chat_message = query_as!(
+            ChatMessage,
+            r#"UPDATE messages SET content = $1, edited_at = current_timestamp WHERE id = $2 AND deleted_at IS NULL RETURNING *, message_attachments(id) AS "attachments!: Json<Vec<Attachment>>""#,
+            content,
+            self.id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        broker.do_send(Broadcast::MessageUpdated(chat_message.clone()));
+        *self = chat_message;
+        Ok(())
+    }
+
+    /// Soft delete the message, it stays in the history as a tombstone
+    pub async fn delete(&mut self, broker: &Addr<Broker>, db: &PgPool) -> Result<(), AzumaError> {
+        let chat_message = query_as!(
+            ChatMessage,
+            r#"UPDATE messages SET deleted_at = current_timestamp WHERE id = $1 AND deleted_at IS NULL RETURNING *, message_attachments(id) AS "attachments!: Json<Vec<Attachment>>""#,
+            self.id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(AzumaError::NotFound)?;
+
+        broker.do_send(Broadcast::MessageDeleted {
+            id: chat_message.id,
+            channel: chat_message.channel,
+        });
+        *self = chat_message.into_tombstone();
+        Ok(())
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Strip the content of deleted messages, so they can be served without leaking what was deleted
+    fn into_tombstone(mut self) -> Self {
+        if self.is_deleted() {
+            self.content = String::new();
+            self.attachments = Json(Vec::new());
+        }
+        self
+    }
+
+    /// Get the prior contents of the message, oldest first
+    pub async fn get_revisions(&self, db: &PgPool) -> Result<Vec<MessageRevision>, AzumaError> {
+        let revisions = query_as!(
+            MessageRevision,
+            "SELECT * FROM message_revisions WHERE message = $1 ORDER BY replaced_at ASC",
+            self.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(revisions)
+    }
+
+    /// Fetch a page of the message history of a channel.
+    /// The limit is clamped to [`MAX_HISTORY_LIMIT`] and defaults to [`DEFAULT_HISTORY_LIMIT`].
     pub async fn get_messages(
-        before: Option<&Uuid>,
-        limit: Option<i32>,
+        cursor: HistoryCursor,
+        limit: Option<i64>,
         channel: &Uuid,
         db: &PgPool,
-    ) -> Result<Vec<ChatMessage>, AzumaError> {
-        let chat_messages: Vec<ChatMessage> = query_as!(ChatMessage, "SELECT * FROM messages WHERE created_at < COALESCE((SELECT created_at from messages WHERE id = $1), current_timestamp) AND channel = $2 ORDER BY created_at ASC LIMIT LEAST(100, COALESCE($3, 50))", before, channel, limit)
-            .fetch_all(db)
-            .await?;
+    ) -> Result<MessageHistory, AzumaError> {
+        let limit = limit
+            .unwrap_or(DEFAULT_HISTORY_LIMIT)
+            .clamp(1, MAX_HISTORY_LIMIT);
+
+        let pivot = match cursor {
+            HistoryCursor::Latest => None,
+            HistoryCursor::Before(id) | HistoryCursor::After(id) | HistoryCursor::Around(id) => {
+                let pivot = ChatMessage::get_by_id(&id, db).await?;
+                // a cursor from another channel would leak messages of that channel
+                if &pivot.channel != channel {
+                    return Err(AzumaError::NotFound);
+                }
+                Some(pivot)
+            }
+        };
+
+        let history = match (cursor, pivot) {
+            (HistoryCursor::Before(_), Some(pivot)) => {
+                Self::get_older(&pivot, limit, channel, db).await?
+            }
+            (HistoryCursor::After(_), Some(pivot)) => {
+                Self::get_newer(&pivot, limit, channel, db).await?
+            }
+            (HistoryCursor::Around(_), Some(pivot)) => {
+                let (older_limit, newer_limit) = MessageHistory::split_around_limit(limit);
+                let older = Self::get_older(&pivot, older_limit, channel, db).await?;
+                let newer = Self::get_newer(&pivot, newer_limit, channel, db).await?;
+                MessageHistory::around(pivot, older, newer)
+            }
+            _ => {
+                let messages = query_as!(
+                    ChatMessage,
+                    r#"SELECT *, message_attachments(id) AS "attachments!: Json<Vec<Attachment>>" FROM messages WHERE channel = $1 ORDER BY created_at DESC, id DESC LIMIT $2"#,
+                    channel,
+                    limit + 1
+                )
+                .fetch_all(db)
+                .await?;
+                MessageHistory::from_overfetched(messages, limit)
+            }
+        };
+
+        Ok(history)
+    }
+
+    /// Fetch up to `limit` messages sent before `pivot`, newest first
+    async fn get_older(
+        pivot: &ChatMessage,
+        limit: i64,
+        channel: &Uuid,
+        db: &PgPool,
+    ) -> Result<MessageHistory, AzumaError> {
+        // messages are ordered by (created_at, id) so messages with equal timestamps are paged consistently
+        let messages = query_as!(
+            ChatMessage,
+            r#"SELECT *, message_attachments(id) AS "attachments!: Json<Vec<Attachment>>" FROM messages WHERE channel = $1 AND (created_at, id) < ($2, $3) ORDER BY created_at DESC, id DESC LIMIT $4"#,
+            channel,
+            pivot.created_at,
+            pivot.id,
+            limit + 1
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(MessageHistory::from_overfetched(messages, limit))
+    }
+
+    /// Fetch up to `limit` messages sent after `pivot`, newest first
+    async fn get_newer(
+        pivot: &ChatMessage,
+        limit: i64,
+        channel: &Uuid,
+        db: &PgPool,
+    ) -> Result<MessageHistory, AzumaError> {
+        // fetch in ascending order, so we get the messages right after the pivot instead of the newest ones
+        let messages = query_as!(
+            ChatMessage,
+            r#"SELECT *, message_attachments(id) AS "attachments!: Json<Vec<Attachment>>" FROM messages WHERE channel = $1 AND (created_at, id) > ($2, $3) ORDER BY created_at ASC, id ASC LIMIT $4"#,
+            channel,
+            pivot.created_at,
+            pivot.id,
+            limit + 1
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut history = MessageHistory::from_overfetched(messages, limit);
+        history.messages.reverse();
+        Ok(history)
+    }
+}
+
+impl MessageHistory {
+    /// Build a page from a query which fetched one more message than requested to find out whether there are more messages
+    fn from_overfetched(mut messages: Vec<ChatMessage>, limit: i64) -> Self {
+        let limit = limit as usize;
+        let has_more = messages.len() > limit;
+        messages.truncate(limit);
+        let messages = messages
+            .into_iter()
+            .map(ChatMessage::into_tombstone)
+            .collect();
+        MessageHistory { messages, has_more }
+    }
+
+    /// How many older and newer messages a page around a pivot message holds, the pivot itself takes up one message of the limit
+    fn split_around_limit(limit: i64) -> (i64, i64) {
+        let older_limit = limit / 2;
+        (older_limit, limit - older_limit - 1)
+    }
+
+    /// Join the pages before and after a pivot message into one page with the pivot in between
+    fn around(pivot: ChatMessage, older: MessageHistory, newer: MessageHistory) -> Self {
+        let mut messages = newer.messages;
+        messages.push(pivot.into_tombstone());
+        messages.extend(older.messages);
+        MessageHistory {
+            messages,
+            has_more: older.has_more || newer.has_more,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    /// Messages sent one second apart, newest first like the history queries return them
+    fn messages(count: usize) -> Vec<ChatMessage> {
+        let now = Utc::now();
+        (0..count)
+            .rev()
+            .map(|n| ChatMessage {
+                id: Uuid::from_u128(n as u128),
+                author: Uuid::nil(),
+                channel: Uuid::nil(),
+                content: n.to_string(),
+                created_at: now + Duration::seconds(n as i64),
+                edited_at: None,
+                deleted_at: None,
+                attachments: Json(Vec::new()),
+            })
+            .collect()
+    }
+
+    fn contents(history: &MessageHistory) -> Vec<&str> {
+        history
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn overfetched_page_is_truncated() {
+        let history = MessageHistory::from_overfetched(messages(4), 3);
+        assert_eq!(contents(&history), ["3", "2", "1"]);
+        assert!(history.has_more);
+    }
+
+    #[test]
+    fn short_page_has_no_more() {
+        let history = MessageHistory::from_overfetched(messages(3), 3);
+        assert_eq!(contents(&history), ["2", "1", "0"]);
+        assert!(!history.has_more);
+
+        let history = MessageHistory::from_overfetched(Vec::new(), 3);
+        assert!(history.messages.is_empty());
+        assert!(!history.has_more);
+    }
+
+    #[test]
+    fn deleted_messages_are_served_as_tombstones() {
+        let mut messages = messages(2);
+        messages[0].deleted_at = Some(Utc::now());
+        let history = MessageHistory::from_overfetched(messages, 2);
+        assert_eq!(contents(&history), ["", "0"]);
+        assert!(history.messages[0].is_deleted());
+    }
+
+    #[test]
+    fn around_limit_leaves_room_for_the_pivot() {
+        for limit in 1..=MAX_HISTORY_LIMIT {
+            let (older, newer) = MessageHistory::split_around_limit(limit);
+            assert!(older >= 0 && newer >= 0);
+            assert_eq!(older + newer + 1, limit);
+        }
+        assert_eq!(MessageHistory::split_around_limit(50), (25, 24));
+    }
+
+    #[test]
+    fn around_puts_the_pivot_between_newer_and_older() {
+        let mut all = messages(5);
+        let older = all.split_off(3);
+        let pivot = all.pop().unwrap();
+        let newer = MessageHistory {
+            messages: all,
+            has_more: false,
+        };
+        let older = MessageHistory {
+            messages: older,
+            has_more: true,
+        };
 
-        Ok(chat_messages)
+        let history = MessageHistory::around(pivot, older, newer);
+        assert_eq!(contents(&history), ["4", "3", "2", "1", "0"]);
+        assert!(history.has_more);
     }
 }
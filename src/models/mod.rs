@@ -2,14 +2,36 @@
 //!
 //!  We try to keep most of the structs (and their trait implementations) here in order to keep it organized
 
+/// Files attached to messages
+pub mod attachment;
+/// Resizing and storing user avatars
+pub mod avatar;
+/// Bans and suspensions of users
+pub mod ban;
+/// Private channels between a small group of users
+pub mod directchannel;
 /// We use a generic error type for all the errors occurring in azumaneo
 pub mod error;
+/// Background removal of expired data
+pub mod gc;
+/// Shareable codes granting access to spaces and channels
+pub mod invitecode;
 /// Textmessage struct and its impls
 pub mod message;
+/// Permission bitfields, channel overwrites and their resolution
+pub mod permission;
+pub mod pub_sub;
+/// Roles granting permissions to their members
+pub mod role;
 /// Session related stuff
 pub mod session;
+/// Spaces grouping channels, roles and their members
+pub mod space;
 pub mod stateactor;
-pub mod pub_sub;
+/// Numbers about the instance shown to its administrators
+pub mod stats;
+/// Backends to store uploaded files in
+pub mod storage;
 /// The textchannel struct representation and all its trait implementations
 pub mod textchannel;
 /// Database and internal representations of a user
@@ -0,0 +1,637 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{BitAnd, BitOr, Not};
+
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar, PgPool};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::role::Role;
+use crate::models::space::Space;
+use crate::models::user::User;
+
+/// A set of permissions, stored as a bitfield in the database and serialized as an integer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(from = "i64", into = "i64")]
+#[sqlx(transparent)]
+pub struct Permissions(i64);
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions(0);
+    /// See a channel and read its message history
+    pub const VIEW_CHANNEL: Permissions = Permissions(1 << 0);
+    /// Send messages in a channel
+    pub const SEND_MESSAGES: Permissions = Permissions(1 << 1);
+    /// Delete messages of other users
+    pub const MANAGE_MESSAGES: Permissions = Permissions(1 << 2);
+    /// Create, update and delete channels and their permission overwrites
+    pub const MANAGE_CHANNELS: Permissions = Permissions(1 << 3);
+    /// Grants every permission and allows managing roles. This can't be granted by channel overwrites
+    pub const ADMINISTRATOR: Permissions = Permissions(1 << 4);
+    /// Update the name, description and visibility of a space and remove its members
+    pub const MANAGE_SPACE: Permissions = Permissions(1 << 5);
+    /// Invite users to a space
+    pub const INVITE_MEMBERS: Permissions = Permissions(1 << 6);
+    pub const ALL: Permissions = Permissions((1 << 7) - 1);
+
+    pub fn bits(self) -> i64 {
+        self.0
+    }
+
+    /// Check whether all of the given permissions are part of this set
+    pub fn contains(self, other: Permissions) -> bool {
+        self & other == other
+    }
+
+    /// Apply a channel overwrite, denied permissions are removed after allowed ones are added so a deny wins over an allow of the same overwrite
+    fn overwrite(self, allow: Permissions, deny: Permissions) -> Permissions {
+        (self | allow) & !deny
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Permissions(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Permissions {
+    type Output = Permissions;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Permissions(self.0 & rhs.0)
+    }
+}
+
+impl Not for Permissions {
+    type Output = Permissions;
+
+    fn not(self) -> Self::Output {
+        Permissions(!self.0 & Permissions::ALL.0)
+    }
+}
+
+impl From<i64> for Permissions {
+    /// Unknown bits are dropped, so clients can't store permissions we don't know about yet
+    fn from(bits: i64) -> Self {
+        Permissions(bits & Permissions::ALL.0)
+    }
+}
+
+impl From<Permissions> for i64 {
+    fn from(permissions: Permissions) -> Self {
+        permissions.0
+    }
+}
+
+/// Permissions allowed or denied to a role or a user in a specific channel, taking precedence over the permissions of the roles
+#[derive(Clone, Debug, Serialize)]
+pub struct PermissionOverwrite {
+    pub channel: Uuid,
+    /// The role or user this overwrite applies to
+    pub target: Uuid,
+    pub allow: Permissions,
+    pub deny: Permissions,
+}
+
+impl PermissionOverwrite {
+    pub async fn get_by_channel(
+        channel: &Uuid,
+        db: &PgPool,
+    ) -> Result<(Vec<Self>, Vec<Self>), AzumaError> {
+        let role_overwrites = query_as!(
+            PermissionOverwrite,
+            r#"SELECT channel, role AS target, allow AS "allow: _", deny AS "deny: _" FROM channel_role_overwrites WHERE channel = $1"#,
+            channel
+        )
+        .fetch_all(db)
+        .await?;
+        let member_overwrites = query_as!(
+            PermissionOverwrite,
+            r#"SELECT channel, member AS target, allow AS "allow: _", deny AS "deny: _" FROM channel_member_overwrites WHERE channel = $1"#,
+            channel
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok((role_overwrites, member_overwrites))
+    }
+
+    pub async fn set_for_role(
+        channel: &Uuid,
+        role: &Uuid,
+        allow: Permissions,
+        deny: Permissions,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let overwrite = query_as!(
+            PermissionOverwrite,
+            r#"INSERT INTO channel_role_overwrites (channel, role, allow, deny) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (channel, role) DO UPDATE SET allow = EXCLUDED.allow, deny = EXCLUDED.deny
+            RETURNING channel, role AS target, allow AS "allow: _", deny AS "deny: _""#,
+            channel,
+            role,
+            allow.bits(),
+            deny.bits()
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(overwrite)
+    }
+
+    pub async fn set_for_member(
+        channel: &Uuid,
+        member: &Uuid,
+        allow: Permissions,
+        deny: Permissions,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let overwrite = query_as!(
+            PermissionOverwrite,
+            r#"INSERT INTO channel_member_overwrites (channel, member, allow, deny) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (channel, member) DO UPDATE SET allow = EXCLUDED.allow, deny = EXCLUDED.deny
+            RETURNING channel, member AS target, allow AS "allow: _", deny AS "deny: _""#,
+            channel,
+            member,
+            allow.bits(),
+            deny.bits()
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(overwrite)
+    }
+
+    pub async fn remove_for_role(
+        channel: &Uuid,
+        role: &Uuid,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let result = query!(
+            "DELETE FROM channel_role_overwrites WHERE channel = $1 AND role = $2",
+            channel,
+            role
+        )
+        .execute(db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(AzumaError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn remove_for_member(
+        channel: &Uuid,
+        member: &Uuid,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let result = query!(
+            "DELETE FROM channel_member_overwrites WHERE channel = $1 AND member = $2",
+            channel,
+            member
+        )
+        .execute(db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(AzumaError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Everything needed to resolve the permissions of a user, loaded at once so checking multiple channels doesn't hit the database again.
+/// Only the spaces and channels it was loaded for are known, the user has no permissions anywhere else
+pub struct UserPermissions {
+    /// Union of the permissions of the roles of the user in each space they are a member of
+    spaces: HashMap<Uuid, Permissions>,
+    /// The space of every channel in these spaces
+    channels: HashMap<Uuid, Uuid>,
+    /// Direct messages the user is a member of
+    direct_channels: HashSet<Uuid>,
+    role_overwrites: Vec<PermissionOverwrite>,
+    member_overwrites: Vec<PermissionOverwrite>,
+}
+
+impl UserPermissions {
+    /// Load the permissions of the user in every space and channel, only needed when subscribing a connection to everything it may see
+    pub async fn load(user: &Uuid, db: &PgPool) -> Result<Self, AzumaError> {
+        Self::load_scoped(user, None, None, db).await
+    }
+
+    /// Load the permissions of the user in a single space and its channels
+    pub async fn load_for_space(
+        user: &Uuid,
+        space: &Uuid,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        Self::load_scoped(user, Some(space), None, db).await
+    }
+
+    /// Load the permissions of the user in a single channel, unknown channels grant no permissions
+    pub async fn load_for_channel(
+        user: &Uuid,
+        channel: &Uuid,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let space = query_scalar!("SELECT space FROM textchannels WHERE id = $1", channel)
+            .fetch_optional(db)
+            .await?
+            .flatten();
+        match space {
+            Some(space) => Self::load_scoped(user, Some(&space), Some(channel), db).await,
+            // direct messages don't belong to a space, only their members have permissions there
+            None => {
+                let is_member = query_scalar!(
+                    "SELECT EXISTS (SELECT 1 FROM direct_members WHERE channel = $1 AND member = $2)",
+                    channel,
+                    user
+                )
+                .fetch_one(db)
+                .await?;
+                let mut permissions = Self::empty();
+                if is_member == Some(true) {
+                    permissions.direct_channels.insert(*channel);
+                }
+                Ok(permissions)
+            }
+        }
+    }
+
+    /// Load the permissions of the user in the given space, or in every space if it's `None`.
+    /// Setting `channel` restricts them further to that channel, which has to be part of the space
+    async fn load_scoped(
+        user: &Uuid,
+        space: Option<&Uuid>,
+        channel: Option<&Uuid>,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let memberships = query!(
+            "SELECT spaces.id, spaces.owner FROM spaces JOIN space_members ON space_members.space = spaces.id
+            WHERE space_members.member = $1 AND ($2::uuid IS NULL OR spaces.id = $2)",
+            user,
+            space
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|space| (space.id, space.owner))
+        .collect::<Vec<_>>();
+        let roles = Role::get_by_member(user, space, db).await?;
+        let role_ids: Vec<Uuid> = roles.iter().map(|role| role.id).collect();
+        let mut spaces = Self::combine_roles(user, &memberships, &roles);
+        // the default space has no owner, the instance administrators manage it instead
+        if spaces.contains_key(&Space::DEFAULT) && User::get_by_id(user, db).await?.admin {
+            spaces.insert(Space::DEFAULT, Permissions::ALL);
+        }
+
+        let space_ids: Vec<Uuid> = spaces.keys().copied().collect();
+        let channels: HashMap<Uuid, Uuid> = query!(
+            r#"SELECT id, space AS "space!" FROM textchannels WHERE space = ANY($1) AND ($2::uuid IS NULL OR id = $2)"#,
+            &space_ids,
+            channel
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.space))
+        .collect();
+        let direct_channels = match space {
+            Some(_) => HashSet::new(),
+            None => query_scalar!("SELECT channel FROM direct_members WHERE member = $1", user)
+                .fetch_all(db)
+                .await?
+                .into_iter()
+                .collect(),
+        };
+
+        // overwrites only matter in the channels loaded above
+        let channel_ids: Vec<Uuid> = channels.keys().copied().collect();
+        let role_overwrites = query_as!(
+            PermissionOverwrite,
+            r#"SELECT channel, role AS target, allow AS "allow: _", deny AS "deny: _" FROM channel_role_overwrites WHERE role = ANY($1) AND channel = ANY($2)"#,
+            &role_ids,
+            &channel_ids
+        )
+        .fetch_all(db)
+        .await?;
+        let member_overwrites = query_as!(
+            PermissionOverwrite,
+            r#"SELECT channel, member AS target, allow AS "allow: _", deny AS "deny: _" FROM channel_member_overwrites WHERE member = $1 AND channel = ANY($2)"#,
+            user,
+            &channel_ids
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(UserPermissions {
+            spaces,
+            channels,
+            direct_channels,
+            role_overwrites,
+            member_overwrites,
+        })
+    }
+
+    /// Combine the permissions of the roles of the user in each of the spaces they are a member of, given with their owner
+    fn combine_roles(
+        user: &Uuid,
+        memberships: &[(Uuid, Option<Uuid>)],
+        roles: &[Role],
+    ) -> HashMap<Uuid, Permissions> {
+        let mut spaces: HashMap<Uuid, Permissions> = memberships
+            .iter()
+            .map(|(space, _)| (*space, Permissions::NONE))
+            .collect();
+        for role in roles {
+            if let Some(permissions) = spaces.get_mut(&role.space) {
+                *permissions = *permissions | role.permissions;
+            }
+        }
+        // the owner of a space can always manage it, no matter which roles they have
+        for (space, _) in memberships
+            .iter()
+            .filter(|(_, owner)| owner.as_ref() == Some(user))
+        {
+            spaces.insert(*space, Permissions::ALL);
+        }
+        spaces
+    }
+
+    /// Permissions of a user who has none anywhere
+    fn empty() -> Self {
+        UserPermissions {
+            spaces: HashMap::new(),
+            channels: HashMap::new(),
+            direct_channels: HashSet::new(),
+            role_overwrites: Vec::new(),
+            member_overwrites: Vec::new(),
+        }
+    }
+
+    pub fn is_member(&self, space: &Uuid) -> bool {
+        self.spaces.contains_key(space)
+    }
+
+    /// The permissions of the user in a space outside of any channel, users who aren't members of the space have none
+    pub fn in_space(&self, space: &Uuid) -> Permissions {
+        match self.spaces.get(space) {
+            Some(permissions) if permissions.contains(Permissions::ADMINISTRATOR) => {
+                Permissions::ALL
+            }
+            Some(permissions) => *permissions,
+            None => Permissions::NONE,
+        }
+    }
+
+    /// The permissions of the user in a channel.
+    /// Overwrites are applied in the order: `everyone` role, the other roles combined, the user itself. At each of these levels a deny wins over an allow.
+    /// Members of a direct message may always view it and send messages, nobody else has any permissions there
+    pub fn in_channel(&self, channel: &Uuid) -> Permissions {
+        if self.direct_channels.contains(channel) {
+            return Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES;
+        }
+        let space = match self.channels.get(channel) {
+            Some(space) => space,
+            None => return Permissions::NONE,
+        };
+        let mut permissions = self.in_space(space);
+        if permissions.contains(Permissions::ADMINISTRATOR) {
+            return permissions;
+        }
+
+        let channel_overwrites = self
+            .role_overwrites
+            .iter()
+            .filter(|overwrite| &overwrite.channel == channel);
+
+        let mut allow = Permissions::NONE;
+        let mut deny = Permissions::NONE;
+        for overwrite in channel_overwrites {
+            // the `everyone` role of a space has the id of the space
+            if &overwrite.target == space {
+                permissions = permissions.overwrite(overwrite.allow, overwrite.deny);
+            } else {
+                allow = allow | overwrite.allow;
+                deny = deny | overwrite.deny;
+            }
+        }
+        permissions = permissions.overwrite(allow, deny);
+
+        if let Some(overwrite) = self
+            .member_overwrites
+            .iter()
+            .find(|overwrite| &overwrite.channel == channel)
+        {
+            permissions = permissions.overwrite(overwrite.allow, overwrite.deny);
+        }
+
+        permissions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    const SPACE: u128 = 1;
+    const CHANNEL: u128 = 2;
+    const USER: u128 = 3;
+    const ROLE: u128 = 4;
+    const OTHER_ROLE: u128 = 5;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn role(role: u128, permissions: Permissions) -> Role {
+        Role {
+            id: id(role),
+            name: String::new(),
+            permissions,
+            space: id(SPACE),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn overwrite(target: u128, allow: Permissions, deny: Permissions) -> PermissionOverwrite {
+        PermissionOverwrite {
+            channel: id(CHANNEL),
+            target: id(target),
+            allow,
+            deny,
+        }
+    }
+
+    /// Permissions of [`USER`] who has `in_space` in the space of [`CHANNEL`]
+    fn user_permissions(
+        in_space: Permissions,
+        role_overwrites: Vec<PermissionOverwrite>,
+        member_overwrites: Vec<PermissionOverwrite>,
+    ) -> UserPermissions {
+        UserPermissions {
+            spaces: HashMap::from([(id(SPACE), in_space)]),
+            channels: HashMap::from([(id(CHANNEL), id(SPACE))]),
+            direct_channels: HashSet::new(),
+            role_overwrites,
+            member_overwrites,
+        }
+    }
+
+    #[test]
+    fn roles_are_combined() {
+        let roles = [
+            role(SPACE, Permissions::VIEW_CHANNEL),
+            role(ROLE, Permissions::SEND_MESSAGES),
+        ];
+        let spaces = UserPermissions::combine_roles(&id(USER), &[(id(SPACE), None)], &roles);
+        assert_eq!(
+            spaces[&id(SPACE)],
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES
+        );
+    }
+
+    #[test]
+    fn owner_has_every_permission() {
+        let roles = [role(SPACE, Permissions::NONE)];
+        let spaces =
+            UserPermissions::combine_roles(&id(USER), &[(id(SPACE), Some(id(USER)))], &roles);
+        assert_eq!(spaces[&id(SPACE)], Permissions::ALL);
+
+        let permissions = user_permissions(
+            spaces[&id(SPACE)],
+            vec![overwrite(
+                SPACE,
+                Permissions::NONE,
+                Permissions::VIEW_CHANNEL,
+            )],
+            vec![overwrite(
+                USER,
+                Permissions::NONE,
+                Permissions::VIEW_CHANNEL,
+            )],
+        );
+        assert_eq!(permissions.in_channel(&id(CHANNEL)), Permissions::ALL);
+    }
+
+    #[test]
+    fn administrator_ignores_overwrites() {
+        let permissions = user_permissions(
+            Permissions::ADMINISTRATOR,
+            vec![overwrite(
+                ROLE,
+                Permissions::NONE,
+                Permissions::SEND_MESSAGES,
+            )],
+            vec![overwrite(
+                USER,
+                Permissions::NONE,
+                Permissions::VIEW_CHANNEL,
+            )],
+        );
+        assert_eq!(permissions.in_space(&id(SPACE)), Permissions::ALL);
+        assert_eq!(permissions.in_channel(&id(CHANNEL)), Permissions::ALL);
+    }
+
+    #[test]
+    fn overwrites_apply_from_everyone_over_roles_to_member() {
+        let default = Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES;
+
+        // roles take precedence over `everyone`
+        let permissions = user_permissions(
+            default,
+            vec![
+                overwrite(SPACE, Permissions::NONE, Permissions::VIEW_CHANNEL),
+                overwrite(ROLE, Permissions::VIEW_CHANNEL, Permissions::NONE),
+            ],
+            vec![],
+        );
+        assert_eq!(permissions.in_channel(&id(CHANNEL)), default);
+
+        // the member overwrite takes precedence over roles
+        let permissions = user_permissions(
+            default,
+            vec![overwrite(
+                ROLE,
+                Permissions::VIEW_CHANNEL,
+                Permissions::NONE,
+            )],
+            vec![overwrite(
+                USER,
+                Permissions::NONE,
+                Permissions::VIEW_CHANNEL,
+            )],
+        );
+        assert_eq!(
+            permissions.in_channel(&id(CHANNEL)),
+            Permissions::SEND_MESSAGES
+        );
+
+        let permissions = user_permissions(
+            default,
+            vec![overwrite(
+                ROLE,
+                Permissions::NONE,
+                Permissions::SEND_MESSAGES,
+            )],
+            vec![overwrite(
+                USER,
+                Permissions::SEND_MESSAGES,
+                Permissions::NONE,
+            )],
+        );
+        assert_eq!(permissions.in_channel(&id(CHANNEL)), default);
+    }
+
+    #[test]
+    fn deny_beats_allow_at_the_same_level() {
+        let default = Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES;
+
+        let permissions = user_permissions(
+            default,
+            vec![
+                overwrite(ROLE, Permissions::SEND_MESSAGES, Permissions::NONE),
+                overwrite(OTHER_ROLE, Permissions::NONE, Permissions::SEND_MESSAGES),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            permissions.in_channel(&id(CHANNEL)),
+            Permissions::VIEW_CHANNEL
+        );
+
+        let permissions = user_permissions(
+            Permissions::NONE,
+            vec![],
+            vec![overwrite(
+                USER,
+                Permissions::VIEW_CHANNEL,
+                Permissions::VIEW_CHANNEL,
+            )],
+        );
+        assert_eq!(permissions.in_channel(&id(CHANNEL)), Permissions::NONE);
+    }
+
+    #[test]
+    fn unknown_channels_grant_nothing() {
+        let permissions = user_permissions(Permissions::ALL, vec![], vec![]);
+        assert_eq!(permissions.in_channel(&id(USER)), Permissions::NONE);
+        assert_eq!(permissions.in_space(&id(USER)), Permissions::NONE);
+    }
+
+    #[test]
+    fn unknown_bits_are_dropped() {
+        let permissions: Permissions =
+            serde_json::from_str(&((1i64 << 40) | 1).to_string()).unwrap();
+        assert_eq!(permissions, Permissions::VIEW_CHANNEL);
+
+        let permissions: Permissions = serde_json::from_str("-1").unwrap();
+        assert_eq!(permissions, Permissions::ALL);
+    }
+}
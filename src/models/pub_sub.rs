@@ -23,7 +23,7 @@ impl<S: Clone + Eq + Hash, T: Clone + Eq + Hash> PubSub<S, T> {
     }
 
     pub fn sub(&mut self, subscriber: &S, topic: &T) {
-        if let Some(t) = self.subscribers.get_mut(&subscriber) {
+        if let Some(t) = self.subscribers.get_mut(subscriber) {
             t.insert(topic.clone());
         } else {
             let mut t = HashSet::new();
@@ -31,7 +31,7 @@ impl<S: Clone + Eq + Hash, T: Clone + Eq + Hash> PubSub<S, T> {
             self.subscribers.insert(subscriber.clone(), t);
         }
 
-        if let Some(s) = self.topics.get_mut(&topic) {
+        if let Some(s) = self.topics.get_mut(topic) {
             s.insert(subscriber.clone());
         } else {
             let mut s = HashSet::new();
@@ -50,6 +50,17 @@ impl<S: Clone + Eq + Hash, T: Clone + Eq + Hash> PubSub<S, T> {
         }
     }
 
+    /// Remove a topic including all of its subscriptions
+    pub fn remove_topic(&mut self, topic: &T) {
+        if let Some(s) = self.topics.remove(topic) {
+            for subscriber in s.iter() {
+                if let Some(t) = self.subscribers.get_mut(subscriber) {
+                    t.remove(topic);
+                }
+            }
+        }
+    }
+
     pub fn unsub_all(&mut self, subscriber: &S) {
         if let Some(t) = self.subscribers.get_mut(subscriber) {
             for topic in t.iter() {
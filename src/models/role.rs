@@ -0,0 +1,164 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{query, query_as, query_scalar, PgPool};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::permission::Permissions;
+
+/// A role grants its members a set of [`Permissions`] in the space it belongs to
+#[derive(Clone, Debug, Serialize)]
+pub struct Role {
+    pub id: Uuid,
+    pub name: String,
+    pub permissions: Permissions,
+    pub space: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Role {
+    pub async fn new(
+        space: &Uuid,
+        name: &str,
+        permissions: Permissions,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let role = query_as!(
+            Role,
+            r#"INSERT INTO roles (space, name, permissions) VALUES ($1, $2, $3) RETURNING id, name, permissions AS "permissions: _", space, created_at"#,
+            space,
+            name,
+            permissions.bits()
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(role)
+    }
+
+    pub async fn get_by_id(id: &Uuid, db: &PgPool) -> Result<Self, AzumaError> {
+        let role = query_as!(
+            Role,
+            r#"SELECT id, name, permissions AS "permissions: _", space, created_at FROM roles WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        role.ok_or(AzumaError::NotFound)
+    }
+
+    /// Every member of a space is implicitly a member of its `everyone` role, which has the id of the space
+    pub fn is_everyone(&self) -> bool {
+        self.id == self.space
+    }
+
+    /// Get all roles of a space, including its `everyone` role
+    pub async fn get_by_space(space: &Uuid, db: &PgPool) -> Result<Vec<Self>, AzumaError> {
+        let roles = query_as!(
+            Role,
+            r#"SELECT id, name, permissions AS "permissions: _", space, created_at FROM roles WHERE space = $1 ORDER BY created_at"#,
+            space
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(roles)
+    }
+
+    /// Get all roles of a user in every space, including the `everyone` roles of the spaces they are a member of.
+    /// `space` restricts them to the roles of that space
+    pub async fn get_by_member(
+        member: &Uuid,
+        space: Option<&Uuid>,
+        db: &PgPool,
+    ) -> Result<Vec<Self>, AzumaError> {
+        let roles = query_as!(
+            Role,
+            r#"SELECT id, name, permissions AS "permissions: _", space, created_at FROM roles
+            WHERE ($2::uuid IS NULL OR space = $2)
+            AND (id IN (SELECT space FROM space_members WHERE member = $1) OR id IN (SELECT role FROM role_members WHERE member = $1))"#,
+            member,
+            space
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(roles)
+    }
+
+    pub async fn update(
+        &mut self,
+        name: Option<&str>,
+        permissions: Option<Permissions>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let role = query_as!(
+            Role,
+            r#"UPDATE roles SET name = COALESCE($1, name), permissions = COALESCE($2, permissions) WHERE id = $3
+            RETURNING id, name, permissions AS "permissions: _", space, created_at"#,
+            name,
+            permissions.map(Permissions::bits),
+            self.id
+        )
+        .fetch_one(db)
+        .await?;
+
+        *self = role;
+        Ok(())
+    }
+
+    pub async fn remove(id: &Uuid, db: &PgPool) -> Result<(), AzumaError> {
+        query!("DELETE FROM roles WHERE id = $1", id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the ids of the users who have the role, which are all members of the space for the `everyone` role
+    pub async fn get_members(&self, db: &PgPool) -> Result<Vec<Uuid>, AzumaError> {
+        let members = match self.is_everyone() {
+            true => {
+                query_scalar!(
+                    "SELECT member FROM space_members WHERE space = $1",
+                    self.space
+                )
+                .fetch_all(db)
+                .await?
+            }
+            false => {
+                query_scalar!("SELECT member FROM role_members WHERE role = $1", self.id)
+                    .fetch_all(db)
+                    .await?
+            }
+        };
+
+        Ok(members)
+    }
+
+    pub async fn add_member(&self, member: &Uuid, db: &PgPool) -> Result<(), AzumaError> {
+        query!(
+            "INSERT INTO role_members (role, member) VALUES ($1, $2)",
+            self.id,
+            member
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_member(&self, member: &Uuid, db: &PgPool) -> Result<(), AzumaError> {
+        let result = query!(
+            "DELETE FROM role_members WHERE role = $1 AND member = $2",
+            self.id,
+            member
+        )
+        .execute(db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(AzumaError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
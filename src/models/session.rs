@@ -2,30 +2,70 @@ use std::future::Future;
 use std::pin::Pin;
 
 use actix_web::dev::Payload;
+use actix_web::http::header::USER_AGENT;
 use actix_web::web::Data;
 use actix_web::{FromRequest, HttpRequest};
-use chrono::{DateTime, Utc};
-use sqlx::{query_as, FromRow, PgPool};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{query, query_as, query_scalar, FromRow, PgPool};
 use uuid::Uuid;
 
+use crate::models::ban::Ban;
 use crate::models::error::AzumaError;
 use crate::models::user::User;
 use crate::AzumaState;
 
 #[derive(FromRow, Clone)]
 pub struct Session {
+    /// The secret the client authenticates with, it's never sent to the client again after the login
     pub token: Uuid,
+    /// Refers to the session when listing or revoking it, unlike the token it isn't secret
+    pub id: Uuid,
     pub subject: Uuid,
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    /// The `User-Agent` of the client which created the session
+    pub user_agent: Option<String>,
+    /// The address of the client which created the session
+    pub ip: Option<String>,
+}
+
+/// How long sessions stay valid, configured in the [`AzumaConfig`](crate::AzumaConfig)
+#[derive(Clone, Copy)]
+pub struct SessionLifetime {
+    /// A session expires if it isn't used for this long
+    pub idle_timeout: Duration,
+    /// A session expires this long after it was created, no matter how often it is used
+    pub max_lifetime: Duration,
+    /// A session is renewed at most once in this interval, so not every request writes to the database.
+    /// This also means `last_used_at` is only accurate to this interval
+    pub renewal_interval: Duration,
+}
+
+impl SessionLifetime {
+    /// The point in time a session created at `created_at` expires if it's used at `now`
+    fn expires_at(&self, created_at: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+        (now + self.idle_timeout).min(created_at + self.max_lifetime)
+    }
 }
 
 impl Session {
-    pub async fn new(subject: &User, db: &PgPool) -> Result<Self, AzumaError> {
+    pub async fn new(
+        subject: &User,
+        user_agent: Option<&str>,
+        ip: Option<&str>,
+        lifetime: &SessionLifetime,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let now = Utc::now();
         let session = query_as!(
             Session,
-            "INSERT INTO sessions (subject) values ($1) RETURNING *",
-            subject.id
+            "INSERT INTO sessions (subject, user_agent, ip, created_at, last_used_at, expires_at) values ($1, $2, $3, $4, $4, $5) RETURNING *",
+            subject.id,
+            user_agent,
+            ip,
+            now,
+            lifetime.expires_at(now, now)
         )
         .fetch_one(db)
         .await?;
@@ -33,10 +73,113 @@ impl Session {
         Ok(session)
     }
 
-    pub async fn get_and_renew(token: &Uuid, db: &PgPool) -> Result<Self, AzumaError> {
+    /// Create a session for the client which sent the request
+    pub async fn from_login(
+        subject: &User,
+        req: &HttpRequest,
+        lifetime: &SessionLifetime,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok());
+        let ip = req
+            .connection_info()
+            .realip_remote_addr()
+            .map(ToOwned::to_owned);
+        Session::new(subject, user_agent, ip.as_deref(), lifetime, db).await
+    }
+
+    /// Get all sessions of a user which didn't expire yet, most recently used first
+    pub async fn get_by_subject(subject: &Uuid, db: &PgPool) -> Result<Vec<Self>, AzumaError> {
+        let sessions = query_as!(
+            Session,
+            "SELECT * FROM sessions WHERE subject = $1 AND expires_at > current_timestamp ORDER BY last_used_at DESC",
+            subject
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Delete up to `limit` expired sessions, returns how many were deleted
+    pub async fn remove_expired(limit: i64, db: &PgPool) -> Result<u64, AzumaError> {
+        let result = query!(
+            "DELETE FROM sessions WHERE token IN (SELECT token FROM sessions WHERE expires_at <= current_timestamp LIMIT $1)",
+            limit
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Delete a session of a user by its id, so its token can't be used anymore. Returns the token of the deleted session
+    pub async fn revoke(id: &Uuid, subject: &Uuid, db: &PgPool) -> Result<Uuid, AzumaError> {
+        let token = query_scalar!(
+            "DELETE FROM sessions WHERE id = $1 AND subject = $2 RETURNING token",
+            id,
+            subject
+        )
+        .fetch_optional(db)
+        .await?;
+
+        token.ok_or(AzumaError::NotFound)
+    }
+
+    /// Delete all sessions of a user except the one with the token in `except`, returns the tokens of the deleted sessions
+    pub async fn revoke_all(
+        subject: &Uuid,
+        except: Option<&Uuid>,
+        db: &PgPool,
+    ) -> Result<Vec<Uuid>, AzumaError> {
+        let tokens = query_scalar!(
+            "DELETE FROM sessions WHERE subject = $1 AND token IS DISTINCT FROM $2 RETURNING token",
+            subject,
+            except
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Get a session which didn't expire yet and extend its lifetime if it wasn't renewed within the `renewal_interval`
+    pub async fn get_and_renew(
+        token: &Uuid,
+        lifetime: &SessionLifetime,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let session = query_as!(
+            Session,
+            "SELECT * FROM sessions WHERE token = $1 AND expires_at > current_timestamp",
+            token
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(AzumaError::NotFound)?;
+
+        // sessions of banned users are kept, so they work again once a suspension expired
+        if Ban::get_active(&session.subject, db).await?.is_some() {
+            return Err(AzumaError::Forbidden);
+        }
+
+        let now = Utc::now();
+        // sessions created before the max lifetime was lowered could still expire later
+        if session.created_at + lifetime.max_lifetime <= now {
+            return Err(AzumaError::NotFound);
+        }
+        if now - session.last_used_at < lifetime.renewal_interval {
+            return Ok(session);
+        }
+
         let session = query_as!(
             Session,
-            "UPDATE sessions SET expires_at = current_timestamp + (14 * interval '1 day') WHERE token = $1 AND expires_at > current_timestamp RETURNING *",
+            "UPDATE sessions SET expires_at = $1, last_used_at = $2 WHERE token = $3 AND expires_at > current_timestamp RETURNING *",
+            lifetime.expires_at(session.created_at, now),
+            now,
             token
         )
         .fetch_optional(db)
@@ -68,7 +211,7 @@ impl FromRequest for Session {
                 .expect("app data missing")
                 .as_ref();
 
-            match Session::get_and_renew(&token, &data.db).await {
+            match Session::get_and_renew(&token, &data.config.session_lifetime(), &data.db).await {
                 Ok(session) => Ok(session),
                 Err(AzumaError::NotFound) => Err(AzumaError::Unauthorized),
                 Err(err) => Err(err),
@@ -76,3 +219,44 @@ impl FromRequest for Session {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn lifetime() -> SessionLifetime {
+        SessionLifetime {
+            idle_timeout: Duration::days(7),
+            max_lifetime: Duration::days(30),
+            renewal_interval: Duration::minutes(5),
+        }
+    }
+
+    #[test]
+    fn used_sessions_expire_after_the_idle_timeout() {
+        let created_at = Utc.ymd(2026, 1, 1).and_hms(0, 0, 0);
+        let now = created_at + Duration::days(3);
+        assert_eq!(
+            lifetime().expires_at(created_at, now),
+            now + Duration::days(7)
+        );
+    }
+
+    #[test]
+    fn sessions_never_outlive_the_max_lifetime() {
+        let created_at = Utc.ymd(2026, 1, 1).and_hms(0, 0, 0);
+        let now = created_at + Duration::days(29);
+        assert_eq!(
+            lifetime().expires_at(created_at, now),
+            created_at + Duration::days(30)
+        );
+    }
+
+    #[test]
+    fn new_sessions_expire_after_the_idle_timeout() {
+        let now = Utc.ymd(2026, 1, 1).and_hms(0, 0, 0);
+        assert_eq!(lifetime().expires_at(now, now), now + Duration::days(7));
+    }
+}
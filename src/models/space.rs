@@ -0,0 +1,396 @@
+use actix::Addr;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{query, query_as, query_scalar, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::permission::{Permissions, UserPermissions};
+use crate::models::textchannel::TextChannel;
+use crate::websocket::broker::{Broadcast, Broker, SubSpace, SyncChannels, UnsubSpace};
+
+/// A space groups channels, roles and the users who are allowed to see them.
+/// Users only see the channels of the spaces they are a member of
+#[derive(Clone, Debug, Serialize)]
+pub struct Space {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    /// The owner has every permission in the space and is the only one who can delete it
+    pub owner: Option<Uuid>,
+    /// Everybody can join public spaces, other spaces can only be joined with an invite
+    pub public: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SpaceMember {
+    pub member: Uuid,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// Allows a user to join a space which isn't public, it's used up when they join
+#[derive(Clone, Debug, Serialize)]
+pub struct SpaceInvite {
+    pub space: Uuid,
+    pub invitee: Uuid,
+    pub inviter: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Space {
+    /// Every user joins this space when they register, it contains everything which existed before spaces were introduced
+    pub const DEFAULT: Uuid = Uuid::nil();
+
+    /// Create a space owned by `owner`, who becomes its first member.
+    /// Its `everyone` role allows viewing channels and sending messages
+    pub async fn new(
+        name: &str,
+        description: Option<&str>,
+        public: bool,
+        owner: &Uuid,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<Self, AzumaError> {
+        let mut tx = db.begin().await?;
+        let space = query_as!(
+            Space,
+            "INSERT INTO spaces (name, description, public, owner) VALUES ($1, $2, $3, $4) RETURNING *",
+            name,
+            description,
+            public,
+            owner
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        query!(
+            "INSERT INTO roles (id, space, name, permissions) VALUES ($1, $1, 'everyone', $2)",
+            space.id,
+            (Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES).bits()
+        )
+        .execute(&mut tx)
+        .await?;
+        query!(
+            "INSERT INTO space_members (space, member) VALUES ($1, $2)",
+            space.id,
+            owner
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        broker.do_send(SubSpace {
+            user: *owner,
+            space: space.id,
+            channels: Vec::new(),
+        });
+        Ok(space)
+    }
+
+    pub async fn get_by_id(id: &Uuid, db: &PgPool) -> Result<Self, AzumaError> {
+        let space = query_as!(Space, "SELECT * FROM spaces WHERE id = $1", id)
+            .fetch_optional(db)
+            .await?;
+
+        space.ok_or(AzumaError::NotFound)
+    }
+
+    /// Get all spaces the user is a member of, in the order they joined them
+    pub async fn get_by_member(member: &Uuid, db: &PgPool) -> Result<Vec<Self>, AzumaError> {
+        let spaces = query_as!(
+            Space,
+            "SELECT spaces.* FROM spaces JOIN space_members ON space_members.space = spaces.id WHERE space_members.member = $1 ORDER BY space_members.joined_at",
+            member
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(spaces)
+    }
+
+    /// Update the name, description and/or visibility of the space.
+    /// `Some(None)` as description removes the description
+    pub async fn update(
+        &mut self,
+        name: Option<&str>,
+        description: Option<Option<&str>>,
+        public: Option<bool>,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let name = name.unwrap_or(&self.name);
+        let description = description.unwrap_or(self.description.as_deref());
+        let public = public.unwrap_or(self.public);
+        let space = query_as!(
+            Space,
+            "UPDATE spaces SET name = $1, description = $2, public = $3 WHERE id = $4 RETURNING *",
+            name,
+            description,
+            public,
+            self.id
+        )
+        .fetch_one(db)
+        .await?;
+
+        broker.do_send(Broadcast::SpaceUpdated(space.clone()));
+        *self = space;
+        Ok(())
+    }
+
+    /// Delete the space including its channels, their message history and its roles
+    pub async fn remove(id: &Uuid, broker: &Addr<Broker>, db: &PgPool) -> Result<(), AzumaError> {
+        let mut tx = db.begin().await?;
+        // locking the space keeps channels from being created in it until it's gone
+        query!("SELECT id FROM spaces WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or(AzumaError::NotFound)?;
+        let channels = query_scalar!("SELECT id FROM textchannels WHERE space = $1", id)
+            .fetch_all(&mut tx)
+            .await?;
+        query!("DELETE FROM messages WHERE channel = ANY($1)", &channels)
+            .execute(&mut tx)
+            .await?;
+        // the channels are deleted by the cascade
+        query!("DELETE FROM spaces WHERE id = $1", id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        broker.do_send(Broadcast::SpaceDeleted { id: *id, channels });
+        Ok(())
+    }
+
+    pub async fn is_member(&self, member: &Uuid, db: &PgPool) -> Result<bool, AzumaError> {
+        let is_member = query_scalar!(
+            "SELECT EXISTS (SELECT 1 FROM space_members WHERE space = $1 AND member = $2)",
+            self.id,
+            member
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(is_member == Some(true))
+    }
+
+    pub async fn get_members(&self, db: &PgPool) -> Result<Vec<SpaceMember>, AzumaError> {
+        let members = query_as!(
+            SpaceMember,
+            "SELECT member, joined_at FROM space_members WHERE space = $1 ORDER BY joined_at",
+            self.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(members)
+    }
+
+    /// Make the user a member of the space, using up their invite if they have one.
+    /// Their connections are subscribed to the space and the channels they may view
+    pub async fn add_member(
+        &self,
+        member: &Uuid,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let mut tx = db.begin().await?;
+        if !Self::insert_member(&self.id, member, &mut tx).await? {
+            return Err(AzumaError::AlreadyExists);
+        }
+        tx.commit().await?;
+
+        self.announce_member(member, broker, db).await
+    }
+
+    /// Make the user a member of the space as part of a larger transaction, using up their invite if they have one.
+    /// Returns `false` if they are a member already, otherwise [`announce_member`](Self::announce_member) has to be called once the transaction is committed
+    pub async fn insert_member(
+        space: &Uuid,
+        member: &Uuid,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, AzumaError> {
+        let result = query!(
+            "INSERT INTO space_members (space, member) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            space,
+            member
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        query!(
+            "DELETE FROM space_invites WHERE space = $1 AND invitee = $2",
+            space,
+            member
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Subscribe the connections of a new member to the space and the channels they may view and tell the other members about them
+    pub async fn announce_member(
+        &self,
+        member: &Uuid,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let permissions = UserPermissions::load_for_space(member, &self.id, db).await?;
+        let channels = TextChannel::get_all(db, &self.id)
+            .await?
+            .into_iter()
+            .map(|channel| channel.id)
+            .filter(|channel| {
+                permissions
+                    .in_channel(channel)
+                    .contains(Permissions::VIEW_CHANNEL)
+            })
+            .collect();
+        broker.do_send(SubSpace {
+            user: *member,
+            space: self.id,
+            channels,
+        });
+        broker.do_send(Broadcast::SpaceMemberJoined {
+            space: self.id,
+            user: *member,
+        });
+        Ok(())
+    }
+
+    /// Resubscribe the connections of the members to the channels of the space after their permissions changed,
+    /// so they receive the events of exactly the channels they may view now. `channel` limits this to a single channel of the space
+    pub async fn sync_subscriptions(
+        space: &Uuid,
+        members: &[Uuid],
+        channel: Option<&Uuid>,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let channels: Vec<Uuid> = match channel {
+            Some(channel) => vec![*channel],
+            None => TextChannel::get_all(db, space)
+                .await?
+                .into_iter()
+                .map(|channel| channel.id)
+                .collect(),
+        };
+        for member in members {
+            let permissions = match channel {
+                Some(channel) => UserPermissions::load_for_channel(member, channel, db).await?,
+                None => UserPermissions::load_for_space(member, space, db).await?,
+            };
+            let (visible, hidden): (Vec<Uuid>, Vec<Uuid>) = channels.iter().partition(|channel| {
+                permissions
+                    .in_channel(channel)
+                    .contains(Permissions::VIEW_CHANNEL)
+            });
+            broker.do_send(SyncChannels {
+                user: *member,
+                visible,
+                hidden,
+            });
+        }
+        Ok(())
+    }
+
+    /// Remove the user from the space along with their roles and channel overwrites in it.
+    /// Their connections are unsubscribed from the space and all of its channels
+    pub async fn remove_member(
+        &self,
+        member: &Uuid,
+        broker: &Addr<Broker>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let mut tx = db.begin().await?;
+        let result = query!(
+            "DELETE FROM space_members WHERE space = $1 AND member = $2",
+            self.id,
+            member
+        )
+        .execute(&mut tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AzumaError::NotFound);
+        }
+        query!(
+            "DELETE FROM role_members WHERE member = $1 AND role IN (SELECT id FROM roles WHERE space = $2)",
+            member,
+            self.id
+        )
+        .execute(&mut tx)
+        .await?;
+        let channels = query_scalar!("SELECT id FROM textchannels WHERE space = $1", self.id)
+            .fetch_all(&mut tx)
+            .await?;
+        query!(
+            "DELETE FROM channel_member_overwrites WHERE member = $1 AND channel = ANY($2)",
+            member,
+            &channels
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        // the member is notified as well, before losing access to the space
+        broker.do_send(Broadcast::SpaceMemberLeft {
+            space: self.id,
+            user: *member,
+        });
+        broker.do_send(UnsubSpace {
+            user: *member,
+            space: self.id,
+            channels,
+        });
+        Ok(())
+    }
+
+    /// Allow the user to join the space, inviting them again replaces the previous invite
+    pub async fn invite(
+        &self,
+        invitee: &Uuid,
+        inviter: &Uuid,
+        db: &PgPool,
+    ) -> Result<SpaceInvite, AzumaError> {
+        let invite = query_as!(
+            SpaceInvite,
+            "INSERT INTO space_invites (space, invitee, inviter) VALUES ($1, $2, $3)
+            ON CONFLICT (space, invitee) DO UPDATE SET inviter = EXCLUDED.inviter, created_at = current_timestamp
+            RETURNING *",
+            self.id,
+            invitee,
+            inviter
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(invite)
+    }
+
+    pub async fn has_invite(&self, invitee: &Uuid, db: &PgPool) -> Result<bool, AzumaError> {
+        let invited = query_scalar!(
+            "SELECT EXISTS (SELECT 1 FROM space_invites WHERE space = $1 AND invitee = $2)",
+            self.id,
+            invitee
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(invited == Some(true))
+    }
+
+    /// Get the pending invites of a user, newest first
+    pub async fn get_invites(invitee: &Uuid, db: &PgPool) -> Result<Vec<SpaceInvite>, AzumaError> {
+        let invites = query_as!(
+            SpaceInvite,
+            "SELECT * FROM space_invites WHERE invitee = $1 ORDER BY created_at DESC",
+            invitee
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(invites)
+    }
+}
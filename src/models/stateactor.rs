@@ -1,30 +1,134 @@
-use std::cell::RefCell;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::mem::drop;
+use std::time::{Duration, Instant};
 
 use actix::dev::{MessageResponse, OneshotSender};
-use actix::{Actor, Addr, Context, Handler, Message};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use super::error::AzumaError;
-use crate::websocket::connection::Ws;
+use crate::models::user::CustomStatus;
+use crate::models::ws::{CLOSE_BANNED, CLOSE_SESSION_REVOKED};
+use crate::websocket::broker::{Broadcast, Broker};
+use crate::websocket::connection::{Disconnect, Ws};
 
 /// `StateActor` holds all the runtime required data, which is not needed in a permanent database (e.g. because someone can't be online if the server isn't)
 pub struct StateActor {
+    /// The status shown for every user with at least one connection, aggregated from the statuses of the connections
     onlinestatus: HashMap<Uuid, OnlineStatus>,
-    usersessions: HashMap<Uuid, RefCell<HashMap<Uuid, Addr<Ws>>>>,
+    /// The websocket connections of every user by their connection id
+    usersessions: HashMap<Uuid, HashMap<Uuid, UserSession>>,
+    /// The custom status of every user with at least one connection, kept to include it in presence updates
+    customstatus: HashMap<Uuid, CustomStatus>,
+    broker: Addr<Broker>,
+    /// Time without activity after which an `ONLINE` connection counts as `AFK`
+    afk_timeout: Duration,
+}
+
+/// How often connections are checked for exceeding the `afk_timeout` and custom statuses for their expiry
+const AFK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A websocket connection of a user. Every connection reports its own [`OnlineStatus`]
+struct UserSession {
+    addr: Addr<Ws>,
+    /// The token of the [`Session`](crate::models::session::Session) the connection authenticated with
+    token: Uuid,
+    /// The status chosen for this connection
+    status: OnlineStatus,
+    last_activity: Instant,
+    /// Set if the client reported its user as idle
+    idle: bool,
+}
+
+impl UserSession {
+    /// The status of this connection, taking the activity of the user into account.
+    /// Only `ONLINE` turns into `AFK`, an explicitly chosen status like `DND` is kept
+    fn status(&self, afk_timeout: Duration) -> OnlineStatus {
+        match self.status {
+            OnlineStatus::Online if self.idle || self.last_activity.elapsed() >= afk_timeout => {
+                OnlineStatus::Afk
+            }
+            status => status,
+        }
+    }
+
+    /// Choosing a status counts as activity, so a user who switches back to `ONLINE` isn't shown as `AFK` anymore
+    fn set_status(&mut self, status: OnlineStatus) {
+        self.status = status;
+        self.last_activity = Instant::now();
+        self.idle = false;
+    }
 }
 
 impl Actor for StateActor {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(AFK_CHECK_INTERVAL, |act, _ctx| {
+            let users: Vec<Uuid> = act.usersessions.keys().copied().collect();
+            for user in users {
+                act.update_status(user);
+            }
+            act.expire_custom_statuses();
+        });
+    }
 }
 
 impl StateActor {
-    pub fn new() -> Self {
+    pub fn new(broker: Addr<Broker>, afk_timeout: Duration) -> Self {
         StateActor {
             onlinestatus: HashMap::new(),
             usersessions: HashMap::new(),
+            customstatus: HashMap::new(),
+            broker,
+            afk_timeout,
+        }
+    }
+
+    /// Recalculate the status of a user from the statuses of his/her connections and tell the connected clients if it changed
+    fn update_status(&mut self, user: Uuid) {
+        let status = match self.usersessions.get(&user) {
+            Some(sessions) => {
+                OnlineStatus::aggregate(sessions.values().map(|s| s.status(self.afk_timeout)))
+            }
+            None => OnlineStatus::Offline,
+        };
+        let previous = match status {
+            OnlineStatus::Offline => self.onlinestatus.remove(&user),
+            status => self.onlinestatus.insert(user, status),
+        };
+
+        if previous.unwrap_or(OnlineStatus::Offline) != status {
+            self.publish_presence(user);
+        }
+    }
+
+    /// Tell the connected clients about the current status and custom status of a user
+    fn publish_presence(&self, user: Uuid) {
+        self.broker.do_send(Broadcast::PresenceUpdate {
+            user,
+            status: self
+                .onlinestatus
+                .get(&user)
+                .copied()
+                .unwrap_or(OnlineStatus::Offline),
+            custom_status: self.customstatus.get(&user).cloned(),
+            spaces: None,
+        });
+    }
+
+    /// Remove the expired custom statuses of connected users and tell the clients about it
+    fn expire_custom_statuses(&mut self) {
+        let expired: Vec<Uuid> = self
+            .customstatus
+            .iter()
+            .filter(|(_, custom_status)| custom_status.is_expired())
+            .map(|(user, _)| *user)
+            .collect();
+        for user in expired {
+            self.customstatus.remove(&user);
+            self.publish_presence(user);
         }
     }
 }
@@ -44,16 +148,21 @@ pub struct GetOnlineStatus {
 pub struct SetOnlineStatus {
     /// The subject
     pub user: Uuid,
+    /// The connection whose status is set, `None` sets the status of all connections of the user
+    pub connection_id: Option<Uuid>,
     /// New [`OnlineStatus`]
     pub status: OnlineStatus,
 }
 
 #[derive(Message)]
-#[rtype(result = "()")]
-/// Remove the [`OnlineStatus`] of a specific user
-pub struct RemoveOnlineStatus {
+#[rtype(result = "Result<(), AzumaError>")]
+/// Report whether the user of a connection is active, users idle on all of their connections are shown as `AFK`
+pub struct ReportActivity {
     /// The subject
     pub user: Uuid,
+    pub connection_id: Uuid,
+    /// `false` if the client noticed its user went idle
+    pub active: bool,
 }
 
 impl Handler<GetOnlineStatus> for StateActor {
@@ -71,20 +180,39 @@ impl Handler<SetOnlineStatus> for StateActor {
     type Result = Result<(), AzumaError>;
 
     fn handle(&mut self, msg: SetOnlineStatus, _ctx: &mut Self::Context) -> Self::Result {
-        match self.usersessions.get(&msg.user) {
-            Some(_) => (),
+        let sessions = match self.usersessions.get_mut(&msg.user) {
+            Some(sessions) => sessions,
             None => return Err(AzumaError::BadRequest),
         };
-        self.onlinestatus.insert(msg.user, msg.status);
+        match msg.connection_id {
+            Some(connection_id) => sessions
+                .get_mut(&connection_id)
+                .ok_or(AzumaError::BadRequest)?
+                .set_status(msg.status),
+            None => sessions
+                .values_mut()
+                .for_each(|session| session.set_status(msg.status)),
+        }
+        self.update_status(msg.user);
         Ok(())
     }
 }
 
-impl Handler<RemoveOnlineStatus> for StateActor {
-    type Result = ();
+impl Handler<ReportActivity> for StateActor {
+    type Result = Result<(), AzumaError>;
 
-    fn handle(&mut self, msg: RemoveOnlineStatus, _ctx: &mut Self::Context) -> Self::Result {
-        self.onlinestatus.remove(&msg.user);
+    fn handle(&mut self, msg: ReportActivity, _ctx: &mut Self::Context) -> Self::Result {
+        let session = self
+            .usersessions
+            .get_mut(&msg.user)
+            .and_then(|sessions| sessions.get_mut(&msg.connection_id))
+            .ok_or(AzumaError::BadRequest)?;
+        session.idle = !msg.active;
+        if msg.active {
+            session.last_activity = Instant::now();
+        }
+        self.update_status(msg.user);
+        Ok(())
     }
 }
 
@@ -101,7 +229,7 @@ where
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum OnlineStatus {
     /// This is set if a user establishes a connection and there is currently no other ws session connected which already set a onlinestatus used to represent the default state if a user is available
     #[serde(rename = "ONLINE")]
@@ -112,6 +240,7 @@ pub enum OnlineStatus {
     #[serde(rename = "DND")]
     /// Used to represent the `Do not disturb` mode of the client which doesn't display any notifications to the user
     Dnd,
+    /// Everyone except the user itself gets [`OnlineStatus::Offline`] (see [`OnlineStatus::as_seen_by`])
     #[serde(rename = "OFFLINE")]
     #[serde(alias = "APPEAR_AS_OFFLINE")]
     AppearAsOffline,
@@ -124,13 +253,45 @@ pub enum OnlineStatus {
     Offline,
 }
 
+impl OnlineStatus {
+    /// Derive the status shown for a user with multiple connections: the most available status of all connections wins,
+    /// e.g. a user who is `AFK` on one device but `ONLINE` on another one is shown as `ONLINE`
+    pub fn aggregate(statuses: impl Iterator<Item = OnlineStatus>) -> Self {
+        statuses
+            .max_by_key(|status| status.availability())
+            .unwrap_or(OnlineStatus::Offline)
+    }
+
+    fn availability(self) -> u8 {
+        match self {
+            OnlineStatus::Online => 4,
+            OnlineStatus::Dnd => 3,
+            OnlineStatus::Afk => 2,
+            OnlineStatus::AppearAsOffline => 1,
+            OnlineStatus::Offline => 0,
+        }
+    }
+
+    /// The status of `user` as it is shown to `viewer`.
+    /// If we showed [`OnlineStatus::AppearAsOffline`] to others, user a would know whether b is really offline or just appearing as if he/she is
+    pub fn as_seen_by(self, user: &Uuid, viewer: &Uuid) -> Self {
+        match self {
+            OnlineStatus::AppearAsOffline if user != viewer => OnlineStatus::Offline,
+            status => status,
+        }
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 /// Actor message to add a usersession to the stored ones
 pub struct AddUserSession {
     pub subject: Uuid,
     pub connection_id: Uuid,
+    pub token: Uuid,
     pub addr: Addr<Ws>,
+    /// The custom status of the user as stored in the database
+    pub custom_status: Option<CustomStatus>,
 }
 
 #[derive(Message)]
@@ -145,23 +306,116 @@ impl Handler<AddUserSession> for StateActor {
     type Result = ();
 
     fn handle(&mut self, msg: AddUserSession, _ctx: &mut Self::Context) -> Self::Result {
-        let sessions = match self.usersessions.get(&msg.subject) {
-            Some(sessions) => {
-                sessions.borrow_mut().insert(msg.connection_id, msg.addr);
-                sessions.clone()
-            }
-            None => {
-                let sessions: RefCell<HashMap<Uuid, Addr<Ws>>> = RefCell::new(HashMap::new());
-                sessions.borrow_mut().insert(msg.connection_id, msg.addr);
-                sessions
-            }
+        self.usersessions.entry(msg.subject).or_default().insert(
+            msg.connection_id,
+            UserSession {
+                addr: msg.addr,
+                token: msg.token,
+                status: OnlineStatus::Online,
+                last_activity: Instant::now(),
+                idle: false,
+            },
+        );
+        match msg.custom_status {
+            Some(custom_status) => self.customstatus.insert(msg.subject, custom_status),
+            None => self.customstatus.remove(&msg.subject),
         };
+        self.update_status(msg.subject);
+    }
+}
 
-        if let None = self.onlinestatus.get(&msg.subject) {
-            self.onlinestatus.insert(msg.subject, OnlineStatus::Online);
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Close the websocket connections which authenticated with one of the given session tokens, used when sessions are revoked
+pub struct DisconnectSessions {
+    pub user: Uuid,
+    pub tokens: Vec<Uuid>,
+}
+
+impl Handler<DisconnectSessions> for StateActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: DisconnectSessions, _ctx: &mut Self::Context) -> Self::Result {
+        // the connections remove themselves through `RemoveUserSession` once they stopped
+        if let Some(sessions) = self.usersessions.get(&msg.user) {
+            sessions
+                .values()
+                .filter(|session| msg.tokens.contains(&session.token))
+                .for_each(|session| {
+                    session.addr.do_send(Disconnect {
+                        code: CLOSE_SESSION_REVOKED,
+                        reason: "session revoked",
+                    })
+                });
         }
+    }
+}
 
-        self.usersessions.insert(msg.subject, sessions);
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Close all websocket connections of a user, used when they are banned
+pub struct DisconnectUser {
+    pub user: Uuid,
+}
+
+impl Handler<DisconnectUser> for StateActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: DisconnectUser, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(sessions) = self.usersessions.get(&msg.user) {
+            sessions.values().for_each(|session| {
+                session.addr.do_send(Disconnect {
+                    code: CLOSE_BANNED,
+                    reason: "banned",
+                })
+            });
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "usize")]
+/// Count the users with at least one websocket connection
+pub struct CountConnectedUsers;
+
+impl Handler<CountConnectedUsers> for StateActor {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: CountConnectedUsers, _ctx: &mut Self::Context) -> Self::Result {
+        self.usersessions.len()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Tell the connected clients about a changed custom status of a user
+pub struct SetCustomStatus {
+    pub user: Uuid,
+    pub custom_status: Option<CustomStatus>,
+    /// The spaces of the user, the update is sent to their members if the user has no connections
+    pub spaces: Vec<Uuid>,
+}
+
+impl Handler<SetCustomStatus> for StateActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetCustomStatus, _ctx: &mut Self::Context) -> Self::Result {
+        // only connected users are tracked here, the custom status of everybody else is loaded once he/she connects
+        if !self.usersessions.contains_key(&msg.user) {
+            self.broker.do_send(Broadcast::PresenceUpdate {
+                user: msg.user,
+                status: OnlineStatus::Offline,
+                custom_status: msg.custom_status,
+                spaces: Some(msg.spaces),
+            });
+            return;
+        }
+
+        match msg.custom_status {
+            Some(custom_status) => self.customstatus.insert(msg.user, custom_status),
+            None => self.customstatus.remove(&msg.user),
+        };
+        self.publish_presence(msg.user);
     }
 }
 
@@ -169,29 +423,64 @@ impl Handler<RemoveUserSession> for StateActor {
     type Result = ();
 
     fn handle(&mut self, msg: RemoveUserSession, _ctx: &mut Self::Context) -> Self::Result {
-        let sessions = match self.usersessions.get(&msg.subject) {
-            Some(sessions) => {
-                let mut x = sessions.borrow_mut();
-                x.remove(&msg.connection_id);
-                if x.len() == 0 {
-                    // if tuple return is true, hashset is empty and can be cleaned up from the sessions map
-                    // because that was the only session the user had connected
-                    (x, true)
-                } else {
-                    // if return.1 is false the KV pair mustn't be removed, because the user has other active sessions
-                    (x, false)
-                }
+        if let Entry::Occupied(mut sessions) = self.usersessions.entry(msg.subject) {
+            sessions.get_mut().remove(&msg.connection_id);
+            // clean up users without connections, so they don't show up as online
+            if sessions.get().is_empty() {
+                sessions.remove();
             }
-            None => {
-                return;
-            }
-        };
-
-        if sessions.1 == true {
-            // drop `sessions` in order to borrow mutable later
-            drop(sessions);
-            self.onlinestatus.remove(&msg.subject);
-            self.usersessions.remove(&msg.subject);
         }
+        self.update_status(msg.subject);
+        // the custom status is still part of the last presence update, so it's dropped afterwards
+        if !self.usersessions.contains_key(&msg.subject) {
+            self.customstatus.remove(&msg.subject);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_available_status_wins() {
+        use OnlineStatus::*;
+        let statuses = [Afk, Online, Dnd];
+        assert_eq!(OnlineStatus::aggregate(statuses.iter().copied()), Online);
+        assert_eq!(OnlineStatus::aggregate([Afk, Dnd].iter().copied()), Dnd);
+        assert_eq!(
+            OnlineStatus::aggregate([AppearAsOffline, Afk].iter().copied()),
+            Afk
+        );
+        assert_eq!(
+            OnlineStatus::aggregate([AppearAsOffline].iter().copied()),
+            AppearAsOffline
+        );
+    }
+
+    #[test]
+    fn no_connections_are_offline() {
+        assert_eq!(
+            OnlineStatus::aggregate(std::iter::empty()),
+            OnlineStatus::Offline
+        );
+    }
+
+    #[test]
+    fn appearing_offline_is_hidden_from_others() {
+        let user = Uuid::from_u128(1);
+        let viewer = Uuid::from_u128(2);
+        assert_eq!(
+            OnlineStatus::AppearAsOffline.as_seen_by(&user, &viewer),
+            OnlineStatus::Offline
+        );
+        assert_eq!(
+            OnlineStatus::AppearAsOffline.as_seen_by(&user, &user),
+            OnlineStatus::AppearAsOffline
+        );
+        assert_eq!(
+            OnlineStatus::Dnd.as_seen_by(&user, &viewer),
+            OnlineStatus::Dnd
+        );
     }
 }
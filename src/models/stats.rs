@@ -0,0 +1,50 @@
+use actix::Addr;
+use serde::Serialize;
+use sqlx::{query, PgPool};
+
+use crate::models::error::AzumaError;
+use crate::models::stateactor::{CountConnectedUsers, StateActor};
+
+/// Some numbers about the instance for its administrators
+#[derive(Serialize)]
+pub struct InstanceStats {
+    pub users: i64,
+    pub disabled_users: i64,
+    pub spaces: i64,
+    pub channels: i64,
+    pub direct_channels: i64,
+    pub messages: i64,
+    /// Sessions which didn't expire yet
+    pub active_sessions: i64,
+    /// Users with at least one websocket connection
+    pub connected_users: usize,
+}
+
+impl InstanceStats {
+    pub async fn load(state: &Addr<StateActor>, db: &PgPool) -> Result<Self, AzumaError> {
+        let counts = query!(
+            r#"SELECT
+            (SELECT count(*) FROM users) AS "users!",
+            (SELECT count(*) FROM users WHERE disabled_at IS NOT NULL) AS "disabled_users!",
+            (SELECT count(*) FROM spaces) AS "spaces!",
+            (SELECT count(*) FROM textchannels WHERE NOT direct) AS "channels!",
+            (SELECT count(*) FROM textchannels WHERE direct) AS "direct_channels!",
+            (SELECT count(*) FROM messages) AS "messages!",
+            (SELECT count(*) FROM sessions WHERE expires_at > current_timestamp) AS "active_sessions!""#
+        )
+        .fetch_one(db)
+        .await?;
+        let connected_users = state.send(CountConnectedUsers).await?;
+
+        Ok(InstanceStats {
+            users: counts.users,
+            disabled_users: counts.disabled_users,
+            spaces: counts.spaces,
+            channels: counts.channels,
+            direct_channels: counts.direct_channels,
+            messages: counts.messages,
+            active_sessions: counts.active_sessions,
+            connected_users,
+        })
+    }
+}
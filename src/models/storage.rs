@@ -0,0 +1,62 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Hex encoded SHA-256 hash of some data, used to address it in a [`Storage`]
+pub fn content_hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Check whether a string could be a hash returned by [`content_hash`], so it can't be abused to leave the storage directory
+pub fn is_content_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// A place to keep uploaded files.
+/// Keys contain the hash of the stored data, so everything stored under a key never changes
+pub trait Storage: Send + Sync {
+    /// Store data under a key, storing the same key twice keeps the existing data
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()>;
+    /// Get the data stored under a key, `None` if there is nothing
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// The default [`Storage`], keeping files in a directory on the local filesystem
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.root.join(key);
+        if path.exists() {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // write to a temporary file first, so an interrupted upload never leaves a partial file under the final key.
+        // Its name is unique, so concurrent uploads of the same data don't write to the same file
+        let temporary_path = path.with_extension(format!("{}.partial", Uuid::new_v4()));
+        fs::write(&temporary_path, data)?;
+        fs::rename(temporary_path, path)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -1,34 +1,44 @@
+use actix::Addr;
 use chrono::{DateTime, Utc};
 use serde::Serialize;
 use sqlx::{query, query_as, PgPool};
 use uuid::Uuid;
 
 use crate::models::error::AzumaError;
+use crate::websocket::broker::{Broadcast, Broker};
 
-// TODO: permission int is still missing
+/// Who may access a channel is controlled by roles and the channels [`PermissionOverwrite`](crate::models::permission::PermissionOverwrite)s
 #[derive(Clone, Debug, Serialize)]
 pub struct TextChannel {
     pub id: Uuid,
     pub name: String,
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// Direct message channels are only accessible to the members of their [`DirectChannel`](crate::models::directchannel::DirectChannel)
+    pub direct: bool,
+    /// The space the channel belongs to, direct messages don't belong to any space
+    pub space: Option<Uuid>,
 }
 
 impl TextChannel {
     pub async fn new(
         db: &PgPool,
+        broker: &Addr<Broker>,
+        space: &Uuid,
         name: &str,
         description: Option<&str>,
     ) -> Result<Self, AzumaError> {
         let text_channel = query_as!(
             TextChannel,
-            "INSERT INTO textchannels (name, description) VALUES ($1, $2) RETURNING *",
+            "INSERT INTO textchannels (space, name, description) VALUES ($1, $2, $3) RETURNING *",
+            space,
             name,
             description
         )
         .fetch_one(db)
         .await?;
 
+        broker.do_send(Broadcast::ChannelCreated(text_channel.clone()));
         Ok(text_channel)
     }
 
@@ -41,16 +51,58 @@ impl TextChannel {
         text_channel.ok_or(AzumaError::NotFound)
     }
 
-    pub async fn get_all(db: &PgPool) -> Result<Vec<Self>, AzumaError> {
-        let text_channels = query_as!(TextChannel, "SELECT * FROM textchannels")
-            .fetch_all(db)
-            .await?;
+    /// Get all channels of a space
+    pub async fn get_all(db: &PgPool, space: &Uuid) -> Result<Vec<Self>, AzumaError> {
+        let text_channels = query_as!(
+            TextChannel,
+            "SELECT * FROM textchannels WHERE space = $1 ORDER BY created_at",
+            space
+        )
+        .fetch_all(db)
+        .await?;
 
         Ok(text_channels)
     }
 
-    pub async fn remove(db: &PgPool, id: &Uuid) -> Result<(), AzumaError> {
-        query!("DELETE FROM textchannels WHERE id = $1", id).execute(db).await?;
+    /// Update the name and/or description of the channel.
+    /// `Some(None)` as description removes the description
+    pub async fn update(
+        &mut self,
+        db: &PgPool,
+        broker: &Addr<Broker>,
+        name: Option<&str>,
+        description: Option<Option<&str>>,
+    ) -> Result<(), AzumaError> {
+        let name = name.unwrap_or(&self.name);
+        let description = description.unwrap_or(self.description.as_deref());
+        let text_channel = query_as!(
+            TextChannel,
+            "UPDATE textchannels SET name = $1, description = $2 WHERE id = $3 RETURNING *",
+            name,
+            description,
+            self.id
+        )
+        .fetch_one(db)
+        .await?;
+
+        broker.do_send(Broadcast::ChannelUpdated(text_channel.clone()));
+        *self = text_channel;
+        Ok(())
+    }
+
+    /// Delete the channel along with its whole message history, including tombstones, revisions and attachments
+    pub async fn remove(db: &PgPool, broker: &Addr<Broker>, id: &Uuid) -> Result<(), AzumaError> {
+        let mut tx = db.begin().await?;
+        // revisions and attachments are removed by the cascade of the messages
+        query!("DELETE FROM messages WHERE channel = $1", id)
+            .execute(&mut tx)
+            .await?;
+        query!("DELETE FROM textchannels WHERE id = $1", id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        broker.do_send(Broadcast::ChannelDeleted { id: *id });
         Ok(())
     }
 }
@@ -1,12 +1,15 @@
 use chrono::{DateTime, Utc};
 use log::info;
-use serde::Serialize;
-use sodiumoxide::crypto::pwhash::argon2id13;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::pwhash::argon2id13::{self, HashedPassword};
 use sqlx::types::Uuid;
-use sqlx::{query_as, FromRow, PgPool};
+use sqlx::{query, query_as, query_scalar, FromRow, PgPool, Postgres, Transaction};
 
 use crate::models::error::{Argon2idError, AzumaError};
 
+/// Key of the advisory lock held while checking whether anybody registered yet
+const FIRST_REGISTRATION_LOCK: i64 = 0x617a756d61;
+
 /// The representation of a user account
 #[derive(Debug, FromRow, Serialize)]
 pub struct User {
@@ -15,12 +18,65 @@ pub struct User {
     #[serde(skip)]
     pub password: Vec<u8>,
     pub created_at: DateTime<Utc>,
-    // TODO: add icon and status properties
+    // the custom status is exposed through `User::custom_status`, which hides it once it expired
+    #[serde(skip)]
+    pub status_text: Option<String>,
+    #[serde(skip)]
+    pub status_emoji: Option<String>,
+    #[serde(skip)]
+    pub status_expires_at: Option<DateTime<Utc>>,
+    /// The hash of the user's [`Avatar`](crate::models::avatar::Avatar), it can be fetched from `/avatar/{hash}`
+    pub avatar: Option<String>,
+    /// Instance administrators can use the `/admin` routes, this is unrelated to the permissions in spaces
+    #[serde(skip)]
+    pub admin: bool,
+    /// Disabled users can't log in anymore
+    #[serde(skip)]
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+/// A status message chosen by the user, shown next to his/her [`OnlineStatus`](crate::models::stateactor::OnlineStatus)
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CustomStatus {
+    pub text: String,
+    #[serde(default)]
+    pub emoji: Option<String>,
+    /// The custom status is removed automatically after this point in time
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CustomStatus {
+    pub const MAX_TEXT_LENGTH: usize = 128;
+    pub const MAX_EMOJI_LENGTH: usize = 32;
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    /// Check the limits of the database columns and reject statuses which would be expired right away
+    pub fn validate(&self) -> Result<(), AzumaError> {
+        let text_valid =
+            !self.text.trim().is_empty() && self.text.chars().count() <= Self::MAX_TEXT_LENGTH;
+        let emoji_valid = self.emoji.as_ref().is_none_or(|emoji| {
+            !emoji.is_empty() && emoji.chars().count() <= Self::MAX_EMOJI_LENGTH
+        });
+
+        match text_valid && emoji_valid && !self.is_expired() {
+            true => Ok(()),
+            false => Err(AzumaError::BadRequest),
+        }
+    }
 }
 
 impl User {
     /// Create a new user in the database and return it
-    pub async fn new(name: &str, password: &str, db: &PgPool) -> Result<Self, AzumaError> {
+    pub async fn new(
+        name: &str,
+        password: &str,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Self, AzumaError> {
         let hashed_password = argon2id13::pwhash(
             password.as_bytes(),
             argon2id13::OPSLIMIT_INTERACTIVE,
@@ -34,7 +90,7 @@ impl User {
             name,
             hashed_password.as_ref()
         )
-        .fetch_one(db)
+        .fetch_one(&mut *tx)
         .await?;
         info!(target: "Access Control", "Created user with name '{}' and id {}", name, user.id);
 
@@ -59,6 +115,78 @@ impl User {
         user.ok_or(AzumaError::NotFound)
     }
 
+    /// Get a page of all users, oldest first
+    pub async fn get_all(limit: i64, offset: i64, db: &PgPool) -> Result<Vec<Self>, AzumaError> {
+        let users = query_as!(
+            User,
+            "SELECT * FROM users ORDER BY created_at, id LIMIT $1 OFFSET $2",
+            limit,
+            offset
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Whether nobody registered yet. Concurrent callers wait for each other's transaction to end,
+    /// so only one of them can see an empty instance
+    pub async fn is_first_registration(
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, AzumaError> {
+        query!(
+            "SELECT FROM pg_advisory_xact_lock($1)",
+            FIRST_REGISTRATION_LOCK
+        )
+        .execute(&mut *tx)
+        .await?;
+        let exists = query_scalar!("SELECT EXISTS (SELECT 1 FROM users)")
+            .fetch_one(&mut *tx)
+            .await?;
+
+        Ok(exists != Some(true))
+    }
+
+    /// Make a user an instance administrator or take it away, used to bootstrap administrators from the config or command line
+    pub async fn set_admin_by_name(name: &str, admin: bool, db: &PgPool) -> Result<(), AzumaError> {
+        let result = query!("UPDATE users SET admin = $1 WHERE name = $2", admin, name)
+            .execute(db)
+            .await?;
+
+        match result.rows_affected() {
+            0 => Err(AzumaError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    /// Disable or enable the account, disabling it doesn't revoke the sessions of the user
+    pub async fn set_disabled(&mut self, disabled: bool, db: &PgPool) -> Result<(), AzumaError> {
+        let user = query_as!(
+            User,
+            "UPDATE users SET disabled_at = CASE WHEN $1 THEN COALESCE(disabled_at, current_timestamp) END WHERE id = $2 RETURNING *",
+            disabled,
+            self.id
+        )
+        .fetch_one(db)
+        .await?;
+
+        *self = user;
+        Ok(())
+    }
+
+    /// Check a password against the stored hash
+    pub fn verify_password(&self, password: &str) -> Result<bool, AzumaError> {
+        let hashed_password = HashedPassword::from_slice(&self.password).ok_or(Argon2idError)?;
+        Ok(argon2id13::pwhash_verify(
+            &hashed_password,
+            password.as_bytes(),
+        ))
+    }
+
     /// Update a user
     pub async fn update(
         &mut self,
@@ -92,4 +220,70 @@ impl User {
         *self = user;
         Ok(())
     }
+
+    /// The custom status of the user, if he/she set one which isn't expired yet
+    pub fn custom_status(&self) -> Option<CustomStatus> {
+        let custom_status = CustomStatus {
+            text: self.status_text.clone()?,
+            emoji: self.status_emoji.clone(),
+            expires_at: self.status_expires_at,
+        };
+
+        match custom_status.is_expired() {
+            true => None,
+            false => Some(custom_status),
+        }
+    }
+
+    /// Set or clear the avatar of a user
+    pub async fn set_avatar(
+        &mut self,
+        avatar: Option<&str>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let user = query_as!(
+            User,
+            "UPDATE users SET avatar = $1 WHERE id = $2 RETURNING *",
+            avatar,
+            self.id
+        )
+        .fetch_one(db)
+        .await?;
+        *self = user;
+        Ok(())
+    }
+
+    /// Clear up to `limit` expired custom statuses, returns how many were cleared.
+    /// Expired custom statuses are hidden anyway, this only frees the space
+    pub async fn clear_expired_custom_statuses(limit: i64, db: &PgPool) -> Result<u64, AzumaError> {
+        let result = query!(
+            "UPDATE users SET status_text = NULL, status_emoji = NULL, status_expires_at = NULL
+            WHERE id IN (SELECT id FROM users WHERE status_expires_at <= current_timestamp LIMIT $1)",
+            limit
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Set or clear the custom status of a user
+    pub async fn set_custom_status(
+        &mut self,
+        custom_status: Option<&CustomStatus>,
+        db: &PgPool,
+    ) -> Result<(), AzumaError> {
+        let user = query_as!(
+            User,
+            "UPDATE users SET status_text = $1, status_emoji = $2, status_expires_at = $3 WHERE id = $4 RETURNING *",
+            custom_status.map(|status| status.text.as_str()),
+            custom_status.and_then(|status| status.emoji.as_deref()),
+            custom_status.and_then(|status| status.expires_at),
+            self.id
+        )
+        .fetch_one(db)
+        .await?;
+        *self = user;
+        Ok(())
+    }
 }
@@ -1,18 +1,164 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::models::directchannel::DirectChannel;
 use crate::models::message::ChatMessage;
+use crate::models::space::Space;
+use crate::models::stateactor::OnlineStatus;
+use crate::models::textchannel::TextChannel;
+use crate::models::user::CustomStatus;
+
+/// Close code sent to connections which didn't authenticate within the configured `auth_timeout`
+pub const CLOSE_AUTHENTICATION_TIMEOUT: u16 = 4001;
+/// Close code sent to connections whose session was revoked, e.g. by logging out
+pub const CLOSE_SESSION_REVOKED: u16 = 4002;
+/// Close code sent to all connections of a user when they are banned
+pub const CLOSE_BANNED: u16 = 4003;
+
+/// Every frame a client sends. The optional `id` is chosen by the client and echoed in the reply to this request
+#[derive(Deserialize)]
+pub struct AwspRequest {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub message: AwspRequestMessage,
+}
+
+impl AwspRequest {
+    /// Try to get the `id` of a request which couldn't be deserialized, so the error reply can still be correlated
+    pub fn recover_id(text: &str) -> Option<String> {
+        let value = serde_json::from_str::<serde_json::Value>(text).ok()?;
+        value.get("id")?.as_str().map(String::from)
+    }
+}
 
 #[derive(Deserialize)]
 #[serde(tag = "type", content = "content")]
 pub enum AwspRequestMessage {
-    Authenticate { token: Uuid },
+    Authenticate {
+        token: Uuid,
+    },
+    /// Receive the events of a channel the connection isn't subscribed to yet
+    Subscribe {
+        channel: Uuid,
+    },
+    /// Stop receiving the events of a channel
+    Unsubscribe {
+        channel: Uuid,
+    },
+    /// Send a chat message, the `nonce` is echoed in the [`AwspResponseMessage::MessageSent`] acknowledgement
+    SendMessage {
+        channel: Uuid,
+        content: String,
+        #[serde(default)]
+        nonce: Option<String>,
+    },
+    /// Set the status of this connection, the status shown to other users is aggregated from all connections of a user
+    SetStatus {
+        status: OnlineStatus,
+    },
+    /// Tell the server the user is interacting with the client, clients should send this at most every few seconds
+    Activity,
+    /// Tell the server the user stopped interacting with the client, the connection counts as `AFK` until the next [`AwspRequestMessage::Activity`]
+    Idle,
 }
 
 #[derive(Serialize)]
 #[serde(tag = "type", content = "content")]
 pub enum AwspResponseMessage {
-    Error { message: String },
+    /// Sent once a connection is established. The server pings clients every `heartbeat_interval` seconds, which is answered by a pong automatically.
+    /// Connections which send no frame at all for `client_timeout` seconds are closed
+    Hello {
+        heartbeat_interval: u64,
+        client_timeout: u64,
+    },
+    Error {
+        message: String,
+    },
     Message(ChatMessage),
+    MessageUpdated(ChatMessage),
+    MessageDeleted {
+        id: Uuid,
+        channel: Uuid,
+    },
+    ChannelCreated(TextChannel),
+    ChannelUpdated(TextChannel),
+    ChannelDeleted {
+        id: Uuid,
+    },
+    /// Only sent to the members of the direct message, who are subscribed to it already
+    DirectChannelCreated(DirectChannel),
+    SpaceUpdated(Space),
+    SpaceDeleted {
+        id: Uuid,
+    },
+    SpaceMemberJoined {
+        space: Uuid,
+        user: Uuid,
+    },
+    SpaceMemberLeft {
+        space: Uuid,
+        user: Uuid,
+    },
+    PresenceUpdate {
+        user: Uuid,
+        status: OnlineStatus,
+        custom_status: Option<CustomStatus>,
+    },
     Welcome,
+    Subscribed {
+        channel: Uuid,
+    },
+    Unsubscribed {
+        channel: Uuid,
+    },
+    /// Acknowledges a [`AwspRequestMessage::SendMessage`] with the id of the created message
+    MessageSent {
+        id: Uuid,
+        nonce: Option<String>,
+    },
+    /// Acknowledges a [`AwspRequestMessage::SetStatus`]
+    StatusSet {
+        status: OnlineStatus,
+    },
+    /// Acknowledges a [`AwspRequestMessage::Activity`] or [`AwspRequestMessage::Idle`]
+    ActivityReported,
+}
+
+/// Tells clients whether a frame answers one of their requests or is an event the server sent on its own
+#[derive(Clone, Copy, Serialize)]
+pub enum AwspResponseKind {
+    #[serde(rename = "REPLY")]
+    Reply,
+    #[serde(rename = "EVENT")]
+    Event,
+}
+
+/// Every frame the server sends
+#[derive(Serialize)]
+pub struct AwspResponse {
+    pub kind: AwspResponseKind,
+    /// The `id` of the request this is a reply to, if the client set one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub message: AwspResponseMessage,
+}
+
+impl AwspResponse {
+    pub fn reply(id: Option<String>, message: AwspResponseMessage) -> Self {
+        AwspResponse {
+            kind: AwspResponseKind::Reply,
+            id,
+            message,
+        }
+    }
+
+    pub fn event(message: AwspResponseMessage) -> Self {
+        AwspResponse {
+            kind: AwspResponseKind::Event,
+            id: None,
+            message,
+        }
+    }
 }
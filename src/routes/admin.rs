@@ -0,0 +1,212 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::ban::Ban;
+use crate::models::error::AzumaError;
+use crate::models::session::Session;
+use crate::models::space::Space;
+use crate::models::stateactor::{DisconnectSessions, DisconnectUser};
+use crate::models::stats::InstanceStats;
+use crate::models::textchannel::TextChannel;
+use crate::models::user::User;
+use crate::routes::guard::Admin;
+use crate::AzumaState;
+
+/// Amount of users returned by a single request if the client doesn't specify a limit
+const DEFAULT_USER_LIMIT: i64 = 100;
+/// Upper bound for the amount of users returned by a single request
+const MAX_USER_LIMIT: i64 = 1000;
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+pub struct ListUsersQuery {
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+#[doc(hidden)]
+#[derive(Serialize)]
+pub struct AdminUserResponse {
+    id: Uuid,
+    name: String,
+    created_at: DateTime<Utc>,
+    admin: bool,
+    disabled_at: Option<DateTime<Utc>>,
+}
+
+impl From<User> for AdminUserResponse {
+    fn from(user: User) -> Self {
+        AdminUserResponse {
+            id: user.id,
+            name: user.name,
+            created_at: user.created_at,
+            admin: user.admin,
+            disabled_at: user.disabled_at,
+        }
+    }
+}
+
+/// List all users of the instance, oldest first
+pub async fn list_users(
+    query: web::Query<ListUsersQuery>,
+    data: web::Data<AzumaState>,
+    _admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_USER_LIMIT)
+        .clamp(1, MAX_USER_LIMIT);
+    let users: Vec<AdminUserResponse> = User::get_all(limit, query.offset.max(0), &data.db)
+        .await?
+        .into_iter()
+        .map(AdminUserResponse::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Disable an account, so the user can't log in anymore, and revoke all of its sessions
+pub async fn disable_user(
+    path: web::Path<Uuid>,
+    data: web::Data<AzumaState>,
+    admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let mut user = User::get_by_id(&path.into_inner(), &data.db).await?;
+    // an administrator locking himself/herself out is most likely a mistake
+    if user.id == admin.user.id {
+        return Err(AzumaError::BadRequest);
+    }
+
+    user.set_disabled(true, &data.db).await?;
+    revoke_sessions(&data, &user.id).await?;
+    info!(target: "Access Control", "Administrator '{admin}' disabled user '{user}'", admin = admin.user.id, user = user.id);
+    Ok(HttpResponse::Ok().json(AdminUserResponse::from(user)))
+}
+
+pub async fn enable_user(
+    path: web::Path<Uuid>,
+    data: web::Data<AzumaState>,
+    admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let mut user = User::get_by_id(&path.into_inner(), &data.db).await?;
+    user.set_disabled(false, &data.db).await?;
+    info!(target: "Access Control", "Administrator '{admin}' enabled user '{user}'", admin = admin.user.id, user = user.id);
+    Ok(HttpResponse::Ok().json(AdminUserResponse::from(user)))
+}
+
+/// Revoke all sessions of a user and close his/her websocket connections
+pub async fn revoke_user_sessions(
+    path: web::Path<Uuid>,
+    data: web::Data<AzumaState>,
+    admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let user = User::get_by_id(&path.into_inner(), &data.db).await?;
+    revoke_sessions(&data, &user.id).await?;
+    info!(target: "Access Control", "Administrator '{admin}' revoked all sessions of user '{user}'", admin = admin.user.id, user = user.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+pub struct BanUserRequest {
+    reason: String,
+    /// Suspends the user until then instead of banning them permanently
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// List all users who are currently banned or suspended
+pub async fn list_bans(
+    data: web::Data<AzumaState>,
+    _admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let bans = Ban::get_all_active(&data.db).await?;
+    Ok(HttpResponse::Ok().json(bans))
+}
+
+/// Ban or suspend a user and close all of their websocket connections
+pub async fn ban_user(
+    path: web::Path<Uuid>,
+    req: web::Json<BanUserRequest>,
+    data: web::Data<AzumaState>,
+    admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let user = User::get_by_id(&path.into_inner(), &data.db).await?;
+    if user.id == admin.user.id
+        || req.reason.trim().is_empty()
+        || req
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(AzumaError::BadRequest);
+    }
+
+    let ban = Ban::new(
+        &user.id,
+        req.reason.trim(),
+        &admin.user.id,
+        req.expires_at,
+        &data.db,
+    )
+    .await?;
+    data.state.do_send(DisconnectUser { user: user.id });
+    info!(target: "Access Control", "Administrator '{admin}' banned user '{user}' until {expires_at:?}", admin = admin.user.id, user = user.id, expires_at = ban.expires_at);
+    Ok(HttpResponse::Ok().json(ban))
+}
+
+pub async fn unban_user(
+    path: web::Path<Uuid>,
+    data: web::Data<AzumaState>,
+    admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let user = path.into_inner();
+    Ban::remove(&user, &data.db).await?;
+    info!(target: "Access Control", "Administrator '{admin}' unbanned user '{user}'", admin = admin.user.id, user = user);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Delete any channel, including direct messages
+pub async fn delete_channel(
+    path: web::Path<Uuid>,
+    data: web::Data<AzumaState>,
+    admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let channel = TextChannel::get_by_id(&data.db, &path.into_inner()).await?;
+    TextChannel::remove(&data.db, &data.broker, &channel.id).await?;
+    info!(target: "REST API", "Administrator '{admin}' deleted TextChannel '{channel}'", admin = admin.user.id, channel = channel.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Delete any space except the default one, every user is a member of that
+pub async fn delete_space(
+    path: web::Path<Uuid>,
+    data: web::Data<AzumaState>,
+    admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let space = path.into_inner();
+    if space == Space::DEFAULT {
+        return Err(AzumaError::BadRequest);
+    }
+    Space::remove(&space, &data.broker, &data.db).await?;
+    info!(target: "REST API", "Administrator '{admin}' deleted space '{space}'", admin = admin.user.id, space = space);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn instance_stats(
+    data: web::Data<AzumaState>,
+    _admin: Admin,
+) -> Result<HttpResponse, AzumaError> {
+    let stats = InstanceStats::load(&data.state, &data.db).await?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+async fn revoke_sessions(data: &AzumaState, user: &Uuid) -> Result<(), AzumaError> {
+    let tokens = Session::revoke_all(user, None, &data.db).await?;
+    data.state.do_send(DisconnectSessions {
+        user: *user,
+        tokens,
+    });
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ContentDisposition, DispositionParam, DispositionType,
+};
+use actix_web::{web, HttpResponse};
+use image::guess_format;
+use uuid::Uuid;
+
+use crate::models::attachment::Attachment;
+use crate::models::error::AzumaError;
+use crate::models::message::ChatMessage;
+use crate::models::permission::Permissions;
+use crate::routes::guard::Guard;
+use crate::AzumaState;
+
+/// Declared mime types which are served as they are, browsers don't run scripts in any of them.
+/// `type/*` allows all non-empty subtypes
+const SAFE_MIME_TYPES: &[&str] = &["text/plain", "application/pdf", "audio/*", "video/*"];
+
+/// The mime type an attachment is served with. The type declared by the uploader is only trusted if it's harmless,
+/// images get the type detected from their data
+fn served_mime_type(declared: &str, data: &[u8]) -> String {
+    if let Ok(format) = guess_format(data) {
+        return format.to_mime_type().to_owned();
+    }
+    let declared = declared.to_ascii_lowercase();
+    let safe = SAFE_MIME_TYPES
+        .iter()
+        .any(|safe| match safe.strip_suffix("/*") {
+            Some(prefix) => declared
+                .split_once('/')
+                .is_some_and(|(kind, subtype)| kind == prefix && !subtype.is_empty()),
+            None => safe == &declared,
+        });
+    match safe {
+        true => declared,
+        false => "application/octet-stream".to_owned(),
+    }
+}
+
+/// Download an attached file, this needs [`Permissions::VIEW_CHANNEL`] in the channel of the message
+pub async fn fetch_attachment(
+    state: web::Data<AzumaState>,
+    path: web::Path<Uuid>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let attachment = Attachment::get_by_id(&path.into_inner(), &state.db).await?;
+    let chat_message = ChatMessage::get_by_id(&attachment.message, &state.db).await?;
+    if chat_message.is_deleted() {
+        return Err(AzumaError::NotFound);
+    }
+    guard
+        .require_in(&chat_message.channel, Permissions::VIEW_CHANNEL)
+        .await?;
+
+    let storage = state.storage.clone();
+    let (attachment, data) = web::block(move || {
+        let data = attachment.load(storage.as_ref())?;
+        Ok::<_, AzumaError>((attachment, data))
+    })
+    .await??;
+
+    // only images are shown inline, everything else is downloaded so uploaded html can't run in the context of the server
+    let mime_type = served_mime_type(&attachment.mime_type, &data);
+    let disposition = match mime_type.starts_with("image/") && attachment.width.is_some() {
+        true => DispositionType::Inline,
+        false => DispositionType::Attachment,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(mime_type)
+        .insert_header(ContentDisposition {
+            disposition,
+            parameters: vec![DispositionParam::Filename(attachment.name)],
+        })
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .insert_header(CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::MaxAge(86_400),
+        ]))
+        .body(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The start of a PNG file, enough for the format to be detected
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn images_get_the_detected_type() {
+        assert_eq!(served_mime_type("text/html", PNG), "image/png");
+        assert_eq!(served_mime_type("image/jpeg", PNG), "image/png");
+    }
+
+    #[test]
+    fn safe_declared_types_are_kept() {
+        assert_eq!(served_mime_type("text/plain", b"hello"), "text/plain");
+        assert_eq!(
+            served_mime_type("Application/PDF", b"%PDF"),
+            "application/pdf"
+        );
+        assert_eq!(served_mime_type("video/mp4", b""), "video/mp4");
+        assert_eq!(served_mime_type("audio/ogg", b""), "audio/ogg");
+    }
+
+    #[test]
+    fn other_declared_types_are_served_as_binary() {
+        for declared in [
+            "text/html",
+            "image/svg+xml",
+            "application/javascript",
+            "audio",
+            "audio/",
+            "",
+        ] {
+            assert_eq!(
+                served_mime_type(declared, b"<script>alert(1)</script>"),
+                "application/octet-stream",
+                "{}",
+                declared
+            );
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::models::avatar::Avatar;
+use crate::models::error::AzumaError;
+use crate::models::session::Session;
+use crate::models::user::User;
+use crate::AzumaState;
+
+#[doc(hidden)]
+#[derive(Serialize)]
+pub struct SetAvatarResponse {
+    avatar: String,
+}
+
+/// Replace the avatar of the current user with the PNG, JPEG or WebP image in the request body
+pub async fn set_avatar(
+    data: web::Data<AzumaState>,
+    body: web::Bytes,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let storage = data.storage.clone();
+    let avatar = web::block(move || Avatar::store(&body, storage.as_ref())).await??;
+
+    let mut user = User::get_by_id(&session.subject, &data.db).await?;
+    user.set_avatar(Some(&avatar.hash), &data.db).await?;
+    info!(target: "REST API", "User '{}' changed his/her avatar to '{}'", user.id, avatar.hash);
+
+    let response_body = SetAvatarResponse {
+        avatar: avatar.hash,
+    };
+    Ok(HttpResponse::Ok().json(response_body))
+}
+
+/// Remove the avatar of the current user, the stored images stay available under their hash
+pub async fn remove_avatar(
+    data: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let mut user = User::get_by_id(&session.subject, &data.db).await?;
+    user.set_avatar(None, &data.db).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+pub struct FetchAvatarQuery {
+    size: Option<u32>,
+}
+
+/// Fetch an avatar as PNG image by the hash found in the [`User`] representation.
+/// This doesn't require a session, so clients can load avatars like any other image
+pub async fn fetch_avatar(
+    data: web::Data<AzumaState>,
+    path: web::Path<String>,
+    query: web::Query<FetchAvatarQuery>,
+) -> Result<HttpResponse, AzumaError> {
+    let hash = path.into_inner();
+    let size = query.size.unwrap_or(Avatar::SIZES[Avatar::SIZES.len() - 1]);
+
+    let storage = data.storage.clone();
+    let image = web::block(move || Avatar::load(&hash, size, storage.as_ref())).await??;
+
+    // the hash changes with the image, so clients never have to revalidate
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(31_536_000),
+            CacheDirective::Extension("immutable".to_owned(), None),
+        ]))
+        .body(image))
+}
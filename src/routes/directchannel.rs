@@ -0,0 +1,48 @@
+use actix_web::web::Json;
+use actix_web::{web, HttpResponse};
+use log::info;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::directchannel::DirectChannel;
+use crate::models::error::AzumaError;
+use crate::models::session::Session;
+use crate::models::user::User;
+use crate::AzumaState;
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+pub struct DirectChannelOpenRequest {
+    /// The users to talk to, the current user is added implicitly
+    members: Vec<Uuid>,
+}
+
+/// Open a direct message with one or more users.
+/// If there is a direct message between exactly these users already, it is returned instead of creating a new one
+pub async fn open_direct_channel(
+    req: Json<DirectChannelOpenRequest>,
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    for member in &req.members {
+        User::get_by_id(member, &state.db).await?;
+    }
+
+    let mut members = req.members.clone();
+    members.push(session.subject);
+    let (direct_channel, created) = DirectChannel::open(&members, &state.broker, &state.db).await?;
+    if !created {
+        return Ok(HttpResponse::Ok().json(direct_channel));
+    }
+    info!(target: "REST API", "User '{user}' opened direct message '{channel}'", user = session.subject, channel = direct_channel.channel.id);
+    Ok(HttpResponse::Created().json(direct_channel))
+}
+
+/// List the direct messages of the current user, newest first
+pub async fn list_direct_channels(
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let direct_channels = DirectChannel::get_by_member(&session.subject, &state.db).await?;
+    Ok(HttpResponse::Ok().json(direct_channels))
+}
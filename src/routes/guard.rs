@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::dev::Payload;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpRequest};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::permission::{Permissions, UserPermissions};
+use crate::models::session::Session;
+use crate::models::user::User;
+use crate::AzumaState;
+
+/// Extractor for routes which need to check the permissions of the requesting user before acting.
+/// It authenticates the request the same way [`Session`] does, the permissions are only loaded for the space or channel a route checks.
+pub struct Guard {
+    pub session: Session,
+    db: PgPool,
+}
+
+impl Guard {
+    /// Load the permissions of the user in the space and its channels
+    pub async fn in_space(&self, space: &Uuid) -> Result<UserPermissions, AzumaError> {
+        UserPermissions::load_for_space(&self.session.subject, space, &self.db).await
+    }
+
+    /// Fail with [`AzumaError::Forbidden`] unless the user has all `required` permissions in the space outside of channels
+    pub async fn require_in_space(
+        &self,
+        space: &Uuid,
+        required: Permissions,
+    ) -> Result<(), AzumaError> {
+        match self
+            .in_space(space)
+            .await?
+            .in_space(space)
+            .contains(required)
+        {
+            true => Ok(()),
+            false => Err(AzumaError::Forbidden),
+        }
+    }
+
+    /// Fail with [`AzumaError::Forbidden`] unless the user has all `required` permissions in the channel
+    pub async fn require_in(
+        &self,
+        channel: &Uuid,
+        required: Permissions,
+    ) -> Result<(), AzumaError> {
+        let permissions =
+            UserPermissions::load_for_channel(&self.session.subject, channel, &self.db).await?;
+        match permissions.in_channel(channel).contains(required) {
+            true => Ok(()),
+            false => Err(AzumaError::Forbidden),
+        }
+    }
+}
+
+impl FromRequest for Guard {
+    type Error = AzumaError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session = Session::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            let session = session.await?;
+            let data = req
+                .app_data::<Data<AzumaState>>()
+                .expect("app data missing")
+                .as_ref();
+
+            Ok(Guard {
+                session,
+                db: data.db.clone(),
+            })
+        })
+    }
+}
+
+/// Extractor for the `/admin` routes, it authenticates the request the same way [`Session`] does
+/// and fails with [`AzumaError::Forbidden`] unless the user is an instance administrator
+pub struct Admin {
+    pub user: User,
+}
+
+impl FromRequest for Admin {
+    type Error = AzumaError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session = Session::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            let session = session.await?;
+            let data = req
+                .app_data::<Data<AzumaState>>()
+                .expect("app data missing")
+                .as_ref();
+            let user = User::get_by_id(&session.subject, &data.db).await?;
+            if !user.admin {
+                return Err(AzumaError::Forbidden);
+            }
+
+            Ok(Admin { user })
+        })
+    }
+}
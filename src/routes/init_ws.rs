@@ -1,7 +1,6 @@
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use log::info;
-use uuid::Uuid;
 
 use crate::websocket::connection::Ws;
 use crate::AzumaState;
@@ -12,15 +11,7 @@ pub(crate) async fn init_ws(
     req: HttpRequest,
     stream: web::Payload,
 ) -> Result<HttpResponse, Error> {
-    let resp = ws::start(
-        Ws {
-            data,
-            subject: None,
-            connection_id: Uuid::new_v4(),
-        },
-        &req,
-        stream,
-    );
+    let resp = ws::start(Ws::new(data), &req, stream);
     info!(target: "REST API", "Upgrading connection to websocket from {}:{}", req.peer_addr().unwrap().ip(), req.peer_addr().unwrap().port());
     resp
 }
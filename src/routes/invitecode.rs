@@ -0,0 +1,159 @@
+use actix_web::web::Json;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::invitecode::InviteCode;
+use crate::models::permission::Permissions;
+use crate::models::session::Session;
+use crate::models::space::Space;
+use crate::models::textchannel::TextChannel;
+use crate::routes::guard::Guard;
+use crate::AzumaState;
+
+#[derive(Deserialize)]
+pub struct InviteCodeCreateRequest {
+    /// Grant access to this channel of the space as well
+    channel: Option<Uuid>,
+    max_uses: Option<i32>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Create an invite code for a space, or a single channel of it
+pub async fn create_invite_code(
+    path: web::Path<Uuid>,
+    req: Json<InviteCodeCreateRequest>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let space = path.into_inner();
+    Space::get_by_id(&space, &state.db).await?;
+    if req.max_uses.is_some_and(|max_uses| max_uses < 1)
+        || req
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(AzumaError::BadRequest);
+    }
+    match &req.channel {
+        Some(channel) => {
+            let textchannel = TextChannel::get_by_id(&state.db, channel).await?;
+            if textchannel.space != Some(space) {
+                return Err(AzumaError::BadRequest);
+            }
+            // nobody may hand out more than they have themselves
+            guard
+                .require_in(
+                    channel,
+                    InviteCode::channel_permissions() | Permissions::INVITE_MEMBERS,
+                )
+                .await?;
+        }
+        None => {
+            guard
+                .require_in_space(&space, Permissions::INVITE_MEMBERS)
+                .await?
+        }
+    }
+
+    let invite_code = InviteCode::new(
+        &space,
+        req.channel.as_ref(),
+        &guard.session.subject,
+        req.max_uses,
+        req.expires_at,
+        &state.db,
+    )
+    .await?;
+    info!(target: "Access Control", "User '{user}' created an invite code for space '{space}'", user = guard.session.subject, space = space);
+    Ok(HttpResponse::Created().json(invite_code))
+}
+
+/// List all invite codes of a space
+pub async fn list_invite_codes(
+    path: web::Path<Uuid>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let space = path.into_inner();
+    Space::get_by_id(&space, &state.db).await?;
+    guard
+        .require_in_space(&space, Permissions::MANAGE_SPACE)
+        .await?;
+
+    let invite_codes = InviteCode::get_by_space(&space, &state.db).await?;
+    Ok(HttpResponse::Ok().json(invite_codes))
+}
+
+#[doc(hidden)]
+#[derive(Serialize)]
+pub struct InviteCodePreviewResponse {
+    code: String,
+    space: Space,
+    channel: Option<Uuid>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Show what an invite code leads to. This doesn't need authentication, so it can be shown before registering
+pub async fn fetch_invite_code(
+    path: web::Path<String>,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let invite_code = InviteCode::get_by_code(&path.into_inner(), &state.db).await?;
+    if !invite_code.is_usable() {
+        return Err(AzumaError::NotFound);
+    }
+
+    let space = Space::get_by_id(&invite_code.space, &state.db).await?;
+    Ok(HttpResponse::Ok().json(InviteCodePreviewResponse {
+        code: invite_code.code,
+        space,
+        channel: invite_code.channel,
+        expires_at: invite_code.expires_at,
+    }))
+}
+
+/// Join the space of an invite code and get access to its channel, if it has one
+pub async fn redeem_invite_code(
+    path: web::Path<String>,
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let invite_code = InviteCode::get_by_code(&path.into_inner(), &state.db).await?;
+    let space = Space::get_by_id(&invite_code.space, &state.db).await?;
+    // don't use up a code which wouldn't grant anything
+    if invite_code.channel.is_none() && space.is_member(&session.subject, &state.db).await? {
+        return Err(AzumaError::AlreadyExists);
+    }
+
+    let mut tx = state.db.begin().await?;
+    let invite_code = InviteCode::claim(&invite_code.code, &mut tx).await?;
+    let joined = invite_code.grant(&session.subject, &mut tx).await?;
+    tx.commit().await?;
+    invite_code
+        .announce_grant(&session.subject, joined, &state.broker, &state.db)
+        .await?;
+    info!(target: "Access Control", "User '{user}' redeemed an invite code for space '{space}'", user = session.subject, space = space.id);
+    Ok(HttpResponse::Ok().json(space))
+}
+
+/// Invite codes can be revoked by their creator and by users who can manage the space
+pub async fn revoke_invite_code(
+    path: web::Path<String>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let invite_code = InviteCode::get_by_code(&path.into_inner(), &state.db).await?;
+    if invite_code.creator != Some(guard.session.subject) {
+        guard
+            .require_in_space(&invite_code.space, Permissions::MANAGE_SPACE)
+            .await?;
+    }
+
+    InviteCode::revoke(&invite_code.code, &state.db).await?;
+    info!(target: "Access Control", "User '{user}' revoked an invite code for space '{space}'", user = guard.session.subject, space = invite_code.space);
+    Ok(HttpResponse::NoContent().finish())
+}
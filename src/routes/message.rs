@@ -1,11 +1,20 @@
-use actix_web::{web, HttpResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::{stream, Stream, StreamExt};
 use log::info;
+use multer::{Constraints, Multipart, SizeLimit};
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use crate::models::attachment::Attachment;
 use crate::models::error::AzumaError;
-use crate::models::message::ChatMessage;
-use crate::models::session::Session;
+use crate::models::message::{ChatMessage, HistoryCursor};
+use crate::models::permission::Permissions;
+use crate::models::textchannel::TextChannel;
+use crate::routes::guard::Guard;
 use crate::AzumaState;
 
 #[doc(hidden)]
@@ -25,11 +34,19 @@ pub struct SendMessageResponse {
 pub async fn send_msg(
     state: web::Data<AzumaState>,
     request: web::Json<SendMessageRequest>,
-    session: Session,
+    guard: Guard,
 ) -> Result<HttpResponse, AzumaError> {
-    info!(target: "REST API", "ChatMessage sent in '{channel}' by '{user}'", channel = request.channel, user = session.subject);
-    ChatMessage::new(
-        &session.subject,
+    TextChannel::get_by_id(&state.db, &request.channel).await?;
+    guard
+        .require_in(
+            &request.channel,
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+        )
+        .await?;
+
+    info!(target: "REST API", "ChatMessage sent in '{channel}' by '{user}'", channel = request.channel, user = guard.session.subject);
+    let chat_message = ChatMessage::new(
+        &guard.session.subject,
         &request.channel,
         &request.content,
         &state.broker,
@@ -37,5 +54,236 @@ pub async fn send_msg(
     )
     .await?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::Ok().json(SendMessageResponse {
+        id: chat_message.id,
+    }))
+}
+
+/// Maximum size of the `content` field of a message with attachments in bytes
+const MAX_CONTENT_SIZE: u64 = 64 * 1024;
+
+/// Forward the request body through a channel, because [`Multipart`] needs a `Send` stream and [`web::Payload`] isn't one.
+/// The channel only holds a few chunks, so the body is read no faster than it's parsed
+fn forward_payload(
+    mut payload: web::Payload,
+) -> impl Stream<Item = Result<Bytes, PayloadError>> + Send {
+    let (tx, rx) = mpsc::channel(4);
+    actix_web::rt::spawn(async move {
+        while let Some(chunk) = payload.next().await {
+            let failed = chunk.is_err();
+            // the receiver is gone once parsing failed or finished
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+/// Send a chat message with files attached. The request is a `multipart/form-data` form with a `channel` field,
+/// an optional `content` field and a file field for every attachment. The `channel` field has to come before the files,
+/// they are stored one after another while the body is read.
+/// The size and type limits of the attachments are configured in the [`AzumaConfig`](crate::AzumaConfig)
+pub async fn send_msg_with_attachments(
+    state: web::Data<AzumaState>,
+    req: HttpRequest,
+    payload: web::Payload,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let boundary = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| multer::parse_boundary(content_type).ok())
+        .ok_or(AzumaError::BadRequest)?;
+    let max_attachment_size = state.config.max_attachment_size as u64;
+    let constraints = Constraints::new().size_limit(
+        SizeLimit::new()
+            // leave some room for the headers of the fields
+            .whole_stream(
+                max_attachment_size * state.config.max_attachments as u64 + 2 * MAX_CONTENT_SIZE,
+            )
+            .per_field(max_attachment_size)
+            .for_field("channel", 64)
+            .for_field("content", MAX_CONTENT_SIZE),
+    );
+    let mut multipart =
+        Multipart::with_constraints(forward_payload(payload), boundary, constraints);
+
+    let mut channel = None;
+    let mut content = String::new();
+    let mut uploads = Vec::new();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .or(Err(AzumaError::BadRequest))?
+    {
+        if let Some(name) = field.file_name() {
+            // nothing is stored before it's clear the user may send messages in the channel
+            if channel.is_none() || uploads.len() >= state.config.max_attachments {
+                return Err(AzumaError::BadRequest);
+            }
+            let name = name.to_owned();
+            let mime_type = field
+                .content_type()
+                .map_or("application/octet-stream".to_owned(), ToString::to_string);
+            if !state.config.allows_attachment_type(&mime_type) {
+                return Err(AzumaError::BadRequest);
+            }
+            let data = field.bytes().await.or(Err(AzumaError::BadRequest))?;
+            let storage = state.storage.clone();
+            let upload =
+                web::block(move || Attachment::store(&name, &mime_type, &data, storage.as_ref()))
+                    .await??;
+            uploads.push(upload);
+            continue;
+        }
+
+        match field.name() {
+            Some("channel") if channel.is_none() => {
+                let text = field.text().await.or(Err(AzumaError::BadRequest))?;
+                let id = Uuid::parse_str(&text)?;
+                TextChannel::get_by_id(&state.db, &id).await?;
+                guard
+                    .require_in(&id, Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES)
+                    .await?;
+                channel = Some(id);
+            }
+            Some("content") => content = field.text().await.or(Err(AzumaError::BadRequest))?,
+            _ => return Err(AzumaError::BadRequest),
+        }
+    }
+
+    let channel = channel.ok_or(AzumaError::BadRequest)?;
+    if content.is_empty() && uploads.is_empty() {
+        return Err(AzumaError::BadRequest);
+    }
+
+    info!(target: "REST API", "ChatMessage with {count} attachments sent in '{channel}' by '{user}'", count = uploads.len(), channel = channel, user = guard.session.subject);
+    let chat_message = ChatMessage::new_with_attachments(
+        &guard.session.subject,
+        &channel,
+        &content,
+        &uploads,
+        &state.broker,
+        &state.db,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(SendMessageResponse {
+        id: chat_message.id,
+    }))
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+pub struct GetMessagesRequest {
+    before: Option<Uuid>,
+    after: Option<Uuid>,
+    around: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+/// Fetch a page of the message history of a channel, newest messages first.
+/// At most one of the `before`, `after` and `around` cursors may be set, if none is set the newest messages are returned.
+pub async fn get_messages(
+    state: web::Data<AzumaState>,
+    path: web::Path<Uuid>,
+    request: web::Query<GetMessagesRequest>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let channel = path.into_inner();
+    let cursor = match (request.before, request.after, request.around) {
+        (None, None, None) => HistoryCursor::Latest,
+        (Some(id), None, None) => HistoryCursor::Before(id),
+        (None, Some(id), None) => HistoryCursor::After(id),
+        (None, None, Some(id)) => HistoryCursor::Around(id),
+        _ => return Err(AzumaError::BadRequest),
+    };
+
+    // make sure we respond with NOT_FOUND instead of an empty history for unknown channels
+    TextChannel::get_by_id(&state.db, &channel).await?;
+    guard
+        .require_in(&channel, Permissions::VIEW_CHANNEL)
+        .await?;
+    let history = ChatMessage::get_messages(cursor, request.limit, &channel, &state.db).await?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+pub struct EditMessageRequest {
+    content: String,
+}
+
+/// Edit the content of a message, only the author of a message is allowed to do this as long as they may still send messages in its channel
+pub async fn edit_msg(
+    state: web::Data<AzumaState>,
+    path: web::Path<Uuid>,
+    request: web::Json<EditMessageRequest>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let mut chat_message = ChatMessage::get_by_id(&path.into_inner(), &state.db).await?;
+    if chat_message.is_deleted() {
+        return Err(AzumaError::NotFound);
+    }
+    if chat_message.author != guard.session.subject {
+        return Err(AzumaError::Forbidden);
+    }
+    guard
+        .require_in(
+            &chat_message.channel,
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+        )
+        .await?;
+
+    chat_message
+        .edit(&request.content, &state.broker, &state.db)
+        .await?;
+    info!(target: "REST API", "ChatMessage '{message}' edited by '{user}'", message = chat_message.id, user = guard.session.subject);
+
+    Ok(HttpResponse::Ok().json(chat_message))
+}
+
+/// Fetch the prior contents of an edited message, oldest first
+pub async fn get_msg_revisions(
+    state: web::Data<AzumaState>,
+    path: web::Path<Uuid>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let chat_message = ChatMessage::get_by_id(&path.into_inner(), &state.db).await?;
+    if chat_message.is_deleted() {
+        return Err(AzumaError::NotFound);
+    }
+    guard
+        .require_in(&chat_message.channel, Permissions::VIEW_CHANNEL)
+        .await?;
+    let revisions = chat_message.get_revisions(&state.db).await?;
+
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+/// Delete a message, this is allowed for the author and users with [`Permissions::MANAGE_MESSAGES`] in the channel
+pub async fn delete_msg(
+    state: web::Data<AzumaState>,
+    path: web::Path<Uuid>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let mut chat_message = ChatMessage::get_by_id(&path.into_inner(), &state.db).await?;
+    if chat_message.is_deleted() {
+        return Err(AzumaError::NotFound);
+    }
+    if chat_message.author != guard.session.subject {
+        guard
+            .require_in(&chat_message.channel, Permissions::MANAGE_MESSAGES)
+            .await?;
+    }
+
+    chat_message.delete(&state.broker, &state.db).await?;
+    info!(target: "REST API", "ChatMessage '{message}' deleted by '{user}'", message = chat_message.id, user = guard.session.subject);
+
+    Ok(HttpResponse::NoContent().finish())
 }
@@ -6,15 +6,33 @@
 #[allow(unused_imports)]
 use crate::models::user::User;
 
+/// Instance administration, only accessible to instance administrators
+pub mod admin;
 /// Fetch some infos about the running azumaneo server version
 pub mod api;
+/// Downloading files attached to messages
+pub mod attachment;
+/// Upload and fetch user avatars
+pub mod avatar;
+/// Opening and listing direct messages
+pub mod directchannel;
+/// Extractor checking the permissions of the requesting user
+pub mod guard;
 /// Upgrade http connection to websocket
 pub mod init_ws;
+/// Creating, revoking and redeeming invite codes
+pub mod invitecode;
 /// Everything related to messages
 pub mod message;
+/// Managing roles and their members
+pub mod role;
+/// Listing and revoking the sessions of the current user
+pub mod session;
+/// Creating, joining and managing spaces
+pub mod space;
 /// Textchannel stuff is stored here
 pub mod textchannel;
 /// Bindings to the internal [`User`] model
 pub mod user;
-///
+/// Set the onlinestatus of the current user
 pub mod userstatus;
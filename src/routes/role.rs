@@ -0,0 +1,145 @@
+use actix_web::web::{Data, Json, Path};
+use actix_web::HttpResponse;
+use log::info;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::permission::Permissions;
+use crate::models::role::Role;
+use crate::models::space::Space;
+use crate::models::user::User;
+use crate::routes::guard::Guard;
+use crate::AzumaState;
+
+/// List all roles of a space
+pub async fn list_roles(
+    path: Path<Uuid>,
+    state: Data<AzumaState>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let space = path.into_inner();
+    Space::get_by_id(&space, &state.db).await?;
+    if !guard.in_space(&space).await?.is_member(&space) {
+        return Err(AzumaError::Forbidden);
+    }
+
+    let roles = Role::get_by_space(&space, &state.db).await?;
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+#[derive(Deserialize)]
+pub struct RoleCreateRequest {
+    name: String,
+    #[serde(default)]
+    permissions: Permissions,
+}
+
+pub async fn create_role(
+    path: Path<Uuid>,
+    req: Json<RoleCreateRequest>,
+    state: Data<AzumaState>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let space = path.into_inner();
+    Space::get_by_id(&space, &state.db).await?;
+    guard
+        .require_in_space(&space, Permissions::ADMINISTRATOR)
+        .await?;
+
+    let role = Role::new(&space, &req.name, req.permissions, &state.db).await?;
+    info!(target: "Access Control", "User '{user}' created role '{role}' with permissions {permissions}", user = guard.session.subject, role = role.id, permissions = role.permissions.bits());
+    Ok(HttpResponse::Created().json(role))
+}
+
+#[derive(Deserialize)]
+pub struct RoleUpdateRequest {
+    name: Option<String>,
+    permissions: Option<Permissions>,
+}
+
+pub async fn update_role(
+    path: Path<Uuid>,
+    req: Json<RoleUpdateRequest>,
+    state: Data<AzumaState>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let mut role = Role::get_by_id(&path.into_inner(), &state.db).await?;
+    guard
+        .require_in_space(&role.space, Permissions::ADMINISTRATOR)
+        .await?;
+
+    role.update(req.name.as_deref(), req.permissions, &state.db)
+        .await?;
+    if req.permissions.is_some() {
+        let members = role.get_members(&state.db).await?;
+        Space::sync_subscriptions(&role.space, &members, None, &state.broker, &state.db).await?;
+    }
+    info!(target: "Access Control", "User '{user}' updated role '{role}'", user = guard.session.subject, role = role.id);
+    Ok(HttpResponse::Ok().json(role))
+}
+
+pub async fn delete_role(
+    path: Path<Uuid>,
+    state: Data<AzumaState>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let role = Role::get_by_id(&path.into_inner(), &state.db).await?;
+    guard
+        .require_in_space(&role.space, Permissions::ADMINISTRATOR)
+        .await?;
+
+    // the `everyone` role is implicit for all members and can't be removed
+    if role.is_everyone() {
+        return Err(AzumaError::BadRequest);
+    }
+    // the members have to be known before the role is gone
+    let members = role.get_members(&state.db).await?;
+    Role::remove(&role.id, &state.db).await?;
+    Space::sync_subscriptions(&role.space, &members, None, &state.broker, &state.db).await?;
+    info!(target: "Access Control", "User '{user}' deleted role '{role}'", user = guard.session.subject, role = role.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn add_role_member(
+    path: Path<(Uuid, Uuid)>,
+    state: Data<AzumaState>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let (role, member) = path.into_inner();
+    let role = Role::get_by_id(&role, &state.db).await?;
+    guard
+        .require_in_space(&role.space, Permissions::ADMINISTRATOR)
+        .await?;
+
+    if role.is_everyone() {
+        return Err(AzumaError::BadRequest);
+    }
+    let member = User::get_by_id(&member, &state.db).await?;
+    // roles can only be given to members of their space
+    let space = Space::get_by_id(&role.space, &state.db).await?;
+    if !space.is_member(&member.id, &state.db).await? {
+        return Err(AzumaError::BadRequest);
+    }
+    role.add_member(&member.id, &state.db).await?;
+    Space::sync_subscriptions(&role.space, &[member.id], None, &state.broker, &state.db).await?;
+    info!(target: "Access Control", "User '{user}' added '{member}' to role '{role}'", user = guard.session.subject, member = member.id, role = role.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn remove_role_member(
+    path: Path<(Uuid, Uuid)>,
+    state: Data<AzumaState>,
+    guard: Guard,
+) -> Result<HttpResponse, AzumaError> {
+    let (role, member) = path.into_inner();
+    let role = Role::get_by_id(&role, &state.db).await?;
+    guard
+        .require_in_space(&role.space, Permissions::ADMINISTRATOR)
+        .await?;
+
+    role.remove_member(&member, &state.db).await?;
+    Space::sync_subscriptions(&role.space, &[member], None, &state.broker, &state.db).await?;
+    info!(target: "Access Control", "User '{user}' removed '{member}' from role '{role}'", user = guard.session.subject, member = member, role = role.id);
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,90 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::session::Session;
+use crate::models::stateactor::DisconnectSessions;
+use crate::AzumaState;
+
+#[doc(hidden)]
+#[derive(Serialize)]
+pub struct SessionResponse {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    last_used_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    /// Whether this is the session used for the request
+    current: bool,
+}
+
+/// Revoke the session used for the request
+pub async fn logout(
+    data: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    revoke(&data, &session.subject, &session.id).await?;
+    info!(target: "Access Control", "User '{}' logged out", session.subject);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List the active sessions of the current user, most recently used first
+pub async fn list_sessions(
+    data: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let sessions = Session::get_by_subject(&session.subject, &data.db).await?;
+    let response_body: Vec<SessionResponse> = sessions
+        .into_iter()
+        .map(|s| SessionResponse {
+            current: s.token == session.token,
+            id: s.id,
+            created_at: s.created_at,
+            last_used_at: s.last_used_at,
+            expires_at: s.expires_at,
+            user_agent: s.user_agent,
+            ip: s.ip,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(response_body))
+}
+
+/// Revoke a single session of the current user
+pub async fn revoke_session(
+    data: web::Data<AzumaState>,
+    path: web::Path<Uuid>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let id = path.into_inner();
+    revoke(&data, &session.subject, &id).await?;
+    info!(target: "Access Control", "User '{}' revoked one of his/her sessions", session.subject);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Revoke all sessions of the current user, including the one used for the request
+pub async fn revoke_all_sessions(
+    data: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let tokens = Session::revoke_all(&session.subject, None, &data.db).await?;
+    data.state.do_send(DisconnectSessions {
+        user: session.subject,
+        tokens,
+    });
+    info!(target: "Access Control", "User '{}' logged out everywhere", session.subject);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Delete a session and close the websocket connections which authenticated with it
+async fn revoke(data: &AzumaState, subject: &Uuid, id: &Uuid) -> Result<(), AzumaError> {
+    let token = Session::revoke(id, subject, &data.db).await?;
+    data.state.do_send(DisconnectSessions {
+        user: *subject,
+        tokens: vec![token],
+    });
+    Ok(())
+}
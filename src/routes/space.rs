@@ -0,0 +1,225 @@
+use actix_web::web::Json;
+use actix_web::{web, HttpResponse};
+use log::info;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::error::AzumaError;
+use crate::models::permission::Permissions;
+use crate::models::session::Session;
+use crate::models::space::Space;
+use crate::models::user::User;
+use crate::routes::guard::Guard;
+use crate::AzumaState;
+
+#[derive(Deserialize)]
+pub struct SpaceCreateRequest {
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    public: bool,
+}
+
+/// Create a space owned by the current user
+pub async fn create_space(
+    req: Json<SpaceCreateRequest>,
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    // Clean up false input which could screw up the database
+    let description =
+        req.description
+            .as_deref()
+            .and_then(|x| if x.trim().is_empty() { None } else { Some(x) });
+
+    let space = Space::new(
+        &req.name,
+        description,
+        req.public,
+        &session.subject,
+        &state.broker,
+        &state.db,
+    )
+    .await?;
+    info!(target: "REST API", "User '{user}' created space '{space}' with name '{name}'", user = session.subject, space = space.id, name = req.name);
+    Ok(HttpResponse::Created().json(space))
+}
+
+/// List the spaces the current user is a member of
+pub async fn list_spaces(
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let spaces = Space::get_by_member(&session.subject, &state.db).await?;
+    Ok(HttpResponse::Ok().json(spaces))
+}
+
+/// Public spaces can be fetched by everyone, so users can look at them before joining
+pub async fn fetch_space(
+    path: web::Path<Uuid>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let space = Space::get_by_id(&path.into_inner(), &state.db).await?;
+    if !space.public && !guard.in_space(&space.id).await?.is_member(&space.id) {
+        return Err(AzumaError::NotFound);
+    }
+    Ok(HttpResponse::Ok().json(space))
+}
+
+#[derive(Deserialize)]
+pub struct SpaceUpdateRequest {
+    name: Option<String>,
+    /// An empty description removes the description of the space
+    description: Option<String>,
+    public: Option<bool>,
+}
+
+pub async fn update_space(
+    path: web::Path<Uuid>,
+    req: Json<SpaceUpdateRequest>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let mut space = Space::get_by_id(&path.into_inner(), &state.db).await?;
+    guard
+        .require_in_space(&space.id, Permissions::MANAGE_SPACE)
+        .await?;
+
+    // Clean up false input which could screw up the database
+    let description =
+        req.description
+            .as_deref()
+            .map(|x| if x.trim().is_empty() { None } else { Some(x) });
+
+    space
+        .update(
+            req.name.as_deref(),
+            description,
+            req.public,
+            &state.broker,
+            &state.db,
+        )
+        .await?;
+    info!(target: "REST API", "User '{user}' updated space '{space}'", user = guard.session.subject, space = space.id);
+    Ok(HttpResponse::Ok().json(space))
+}
+
+/// Only the owner can delete a space
+pub async fn delete_space(
+    path: web::Path<Uuid>,
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let space = Space::get_by_id(&path.into_inner(), &state.db).await?;
+    if space.owner != Some(session.subject) {
+        return Err(AzumaError::Forbidden);
+    }
+
+    Space::remove(&space.id, &state.broker, &state.db).await?;
+    info!(target: "REST API", "User '{user}' deleted space '{space}'", user = session.subject, space = space.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn list_space_members(
+    path: web::Path<Uuid>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let space = Space::get_by_id(&path.into_inner(), &state.db).await?;
+    if !guard.in_space(&space.id).await?.is_member(&space.id) {
+        return Err(AzumaError::Forbidden);
+    }
+
+    let members = space.get_members(&state.db).await?;
+    Ok(HttpResponse::Ok().json(members))
+}
+
+/// Join a public space or a space the user was invited to
+pub async fn join_space(
+    path: web::Path<Uuid>,
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let space = Space::get_by_id(&path.into_inner(), &state.db).await?;
+    if !space.public && !space.has_invite(&session.subject, &state.db).await? {
+        return Err(AzumaError::NotFound);
+    }
+
+    space
+        .add_member(&session.subject, &state.broker, &state.db)
+        .await?;
+    info!(target: "Access Control", "User '{user}' joined space '{space}'", user = session.subject, space = space.id);
+    Ok(HttpResponse::Ok().json(space))
+}
+
+/// The owner can't leave their space, they have to delete it instead
+pub async fn leave_space(
+    path: web::Path<Uuid>,
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let space = Space::get_by_id(&path.into_inner(), &state.db).await?;
+    if space.owner == Some(session.subject) {
+        return Err(AzumaError::BadRequest);
+    }
+
+    space
+        .remove_member(&session.subject, &state.broker, &state.db)
+        .await?;
+    info!(target: "Access Control", "User '{user}' left space '{space}'", user = session.subject, space = space.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn remove_space_member(
+    path: web::Path<(Uuid, Uuid)>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let (space, member) = path.into_inner();
+    let space = Space::get_by_id(&space, &state.db).await?;
+    guard
+        .require_in_space(&space.id, Permissions::MANAGE_SPACE)
+        .await?;
+    if space.owner == Some(member) {
+        return Err(AzumaError::Forbidden);
+    }
+
+    space
+        .remove_member(&member, &state.broker, &state.db)
+        .await?;
+    info!(target: "Access Control", "User '{user}' removed '{member}' from space '{space}'", user = guard.session.subject, member = member, space = space.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Invite a user, who can join the space afterwards even if it isn't public
+pub async fn invite_to_space(
+    path: web::Path<(Uuid, Uuid)>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let (space, invitee) = path.into_inner();
+    let space = Space::get_by_id(&space, &state.db).await?;
+    guard
+        .require_in_space(&space.id, Permissions::INVITE_MEMBERS)
+        .await?;
+    let invitee = User::get_by_id(&invitee, &state.db).await?;
+    if space.is_member(&invitee.id, &state.db).await? {
+        return Err(AzumaError::AlreadyExists);
+    }
+
+    let invite = space
+        .invite(&invitee.id, &guard.session.subject, &state.db)
+        .await?;
+    info!(target: "Access Control", "User '{user}' invited '{invitee}' to space '{space}'", user = guard.session.subject, invitee = invitee.id, space = space.id);
+    Ok(HttpResponse::Ok().json(invite))
+}
+
+/// List the pending invites of the current user
+pub async fn list_space_invites(
+    state: web::Data<AzumaState>,
+    session: Session,
+) -> Result<HttpResponse, AzumaError> {
+    let invites = Space::get_invites(&session.subject, &state.db).await?;
+    Ok(HttpResponse::Ok().json(invites))
+}
@@ -1,12 +1,16 @@
 use actix_web::web::Json;
 use actix_web::{web, HttpResponse};
 use log::info;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::models::error::AzumaError;
-use crate::models::session::Session;
+use crate::models::permission::{PermissionOverwrite, Permissions};
+use crate::models::role::Role;
+use crate::models::space::Space;
 use crate::models::textchannel::TextChannel;
+use crate::models::user::User;
+use crate::routes::guard::Guard;
 use crate::AzumaState;
 
 #[derive(Deserialize)]
@@ -16,33 +20,270 @@ pub struct TextchannelCreateRequest {
 }
 
 pub async fn create_textchannel(
+    path: web::Path<Uuid>,
     req: Json<TextchannelCreateRequest>,
     state: web::Data<AzumaState>,
-    session: Session,
+    guard: Guard,
 ) -> Result<HttpResponse, AzumaError> {
+    let space = path.into_inner();
+    Space::get_by_id(&space, &state.db).await?;
+    guard
+        .require_in_space(&space, Permissions::MANAGE_CHANNELS)
+        .await?;
+
     // Clean up false input which could screw up the database
-    let description = req
-        .description
-        .as_deref()
-        .map(|x| if x.trim().len() == 0 { None } else { Some(x) })
-        .flatten();
-
-    let textchannel = TextChannel::new(&state.db, &req.name, description).await?;
-    info!(target: "REST API", "User '{user}' created TextChannel with name '{name}'", user = session.subject, name = req.name);
+    let description =
+        req.description
+            .as_deref()
+            .and_then(|x| if x.trim().is_empty() { None } else { Some(x) });
+
+    let textchannel =
+        TextChannel::new(&state.db, &state.broker, &space, &req.name, description).await?;
+    info!(target: "REST API", "User '{user}' created TextChannel with name '{name}' in space '{space}'", user = guard.session.subject, name = req.name, space = space);
     Ok(HttpResponse::Created().json(textchannel))
 }
 
+/// List all channels of a space the user is allowed to view
+pub async fn list_textchannels(
+    path: web::Path<Uuid>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let space = path.into_inner();
+    Space::get_by_id(&space, &state.db).await?;
+    let permissions = guard.in_space(&space).await?;
+    if !permissions.is_member(&space) {
+        return Err(AzumaError::Forbidden);
+    }
+
+    let textchannels: Vec<TextChannel> = TextChannel::get_all(&state.db, &space)
+        .await?
+        .into_iter()
+        .filter(|channel| {
+            permissions
+                .in_channel(&channel.id)
+                .contains(Permissions::VIEW_CHANNEL)
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(textchannels))
+}
+
+pub async fn fetch_textchannel(
+    path: web::Path<Uuid>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let channel = path.into_inner();
+    let textchannel = TextChannel::get_by_id(&state.db, &channel).await?;
+    guard
+        .require_in(&channel, Permissions::VIEW_CHANNEL)
+        .await?;
+    Ok(HttpResponse::Ok().json(textchannel))
+}
+
+#[derive(Deserialize)]
+pub struct TextchannelUpdateRequest {
+    name: Option<String>,
+    /// An empty description removes the description of the channel
+    description: Option<String>,
+}
+
+pub async fn update_textchannel(
+    path: web::Path<Uuid>,
+    req: Json<TextchannelUpdateRequest>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let channel = path.into_inner();
+    let mut textchannel = TextChannel::get_by_id(&state.db, &channel).await?;
+    guard
+        .require_in(&channel, Permissions::MANAGE_CHANNELS)
+        .await?;
+
+    // Clean up false input which could screw up the database
+    let description =
+        req.description
+            .as_deref()
+            .map(|x| if x.trim().is_empty() { None } else { Some(x) });
+
+    textchannel
+        .update(&state.db, &state.broker, req.name.as_deref(), description)
+        .await?;
+    info!(target: "REST API", "User '{user}' updated TextChannel '{channel}'", user = guard.session.subject, channel = channel);
+    Ok(HttpResponse::Ok().json(textchannel))
+}
+
 #[derive(Deserialize)]
 pub struct TextchannelDeleteRequest {
     pub id: Uuid,
 }
 
-//TODO: proper authorization
 pub async fn delete_textchannel(
     req: Json<TextchannelDeleteRequest>,
-    _session: Session,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    TextChannel::get_by_id(&state.db, &req.id).await?;
+    guard
+        .require_in(&req.id, Permissions::MANAGE_CHANNELS)
+        .await?;
+
+    TextChannel::remove(&state.db, &state.broker, &req.id).await?;
+    info!(target: "REST API", "User '{user}' deleted TextChannel '{channel}'", user = guard.session.subject, channel = req.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Serialize)]
+pub struct OverwritesResponse {
+    roles: Vec<PermissionOverwrite>,
+    members: Vec<PermissionOverwrite>,
+}
+
+/// List the permission overwrites of a channel
+pub async fn get_overwrites(
+    path: web::Path<Uuid>,
+    guard: Guard,
     state: web::Data<AzumaState>,
 ) -> Result<HttpResponse, AzumaError> {
-    TextChannel::remove(&state.db, &req.id).await?;
+    let channel = path.into_inner();
+    TextChannel::get_by_id(&state.db, &channel).await?;
+    guard
+        .require_in(&channel, Permissions::MANAGE_CHANNELS)
+        .await?;
+
+    let (roles, members) = PermissionOverwrite::get_by_channel(&channel, &state.db).await?;
+    Ok(HttpResponse::Ok().json(OverwritesResponse { roles, members }))
+}
+
+#[derive(Deserialize)]
+pub struct OverwriteSetRequest {
+    #[serde(default)]
+    allow: Permissions,
+    #[serde(default)]
+    deny: Permissions,
+}
+
+impl OverwriteSetRequest {
+    /// Administrator permissions can only be granted by roles, not on a per channel basis
+    fn validate(&self) -> Result<(), AzumaError> {
+        if self.allow.contains(Permissions::ADMINISTRATOR)
+            || self.deny.contains(Permissions::ADMINISTRATOR)
+        {
+            return Err(AzumaError::BadRequest);
+        }
+        Ok(())
+    }
+}
+
+pub async fn set_role_overwrite(
+    path: web::Path<(Uuid, Uuid)>,
+    req: Json<OverwriteSetRequest>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let (channel, role) = path.into_inner();
+    req.validate()?;
+    let textchannel = TextChannel::get_by_id(&state.db, &channel).await?;
+    let role = Role::get_by_id(&role, &state.db).await?;
+    guard
+        .require_in(&channel, Permissions::MANAGE_CHANNELS)
+        .await?;
+    // roles only apply to the channels of their own space
+    if textchannel.space != Some(role.space) {
+        return Err(AzumaError::BadRequest);
+    }
+    let role = role.id;
+
+    let overwrite =
+        PermissionOverwrite::set_for_role(&channel, &role, req.allow, req.deny, &state.db).await?;
+    sync_overwrite_subscriptions(&textchannel, None, &state).await?;
+    info!(target: "Access Control", "User '{user}' set overwrite for role '{role}' in TextChannel '{channel}'", user = guard.session.subject, role = role, channel = channel);
+    Ok(HttpResponse::Ok().json(overwrite))
+}
+
+pub async fn remove_role_overwrite(
+    path: web::Path<(Uuid, Uuid)>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let (channel, role) = path.into_inner();
+    let textchannel = TextChannel::get_by_id(&state.db, &channel).await?;
+    guard
+        .require_in(&channel, Permissions::MANAGE_CHANNELS)
+        .await?;
+
+    PermissionOverwrite::remove_for_role(&channel, &role, &state.db).await?;
+    sync_overwrite_subscriptions(&textchannel, None, &state).await?;
+    info!(target: "Access Control", "User '{user}' removed overwrite for role '{role}' in TextChannel '{channel}'", user = guard.session.subject, role = role, channel = channel);
     Ok(HttpResponse::NoContent().finish())
-}
\ No newline at end of file
+}
+
+pub async fn set_member_overwrite(
+    path: web::Path<(Uuid, Uuid)>,
+    req: Json<OverwriteSetRequest>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let (channel, member) = path.into_inner();
+    req.validate()?;
+    let textchannel = TextChannel::get_by_id(&state.db, &channel).await?;
+    User::get_by_id(&member, &state.db).await?;
+    guard
+        .require_in(&channel, Permissions::MANAGE_CHANNELS)
+        .await?;
+
+    let overwrite =
+        PermissionOverwrite::set_for_member(&channel, &member, req.allow, req.deny, &state.db)
+            .await?;
+    sync_overwrite_subscriptions(&textchannel, Some(&member), &state).await?;
+    info!(target: "Access Control", "User '{user}' set overwrite for user '{member}' in TextChannel '{channel}'", user = guard.session.subject, member = member, channel = channel);
+    Ok(HttpResponse::Ok().json(overwrite))
+}
+
+pub async fn remove_member_overwrite(
+    path: web::Path<(Uuid, Uuid)>,
+    guard: Guard,
+    state: web::Data<AzumaState>,
+) -> Result<HttpResponse, AzumaError> {
+    let (channel, member) = path.into_inner();
+    let textchannel = TextChannel::get_by_id(&state.db, &channel).await?;
+    guard
+        .require_in(&channel, Permissions::MANAGE_CHANNELS)
+        .await?;
+
+    PermissionOverwrite::remove_for_member(&channel, &member, &state.db).await?;
+    sync_overwrite_subscriptions(&textchannel, Some(&member), &state).await?;
+    info!(target: "Access Control", "User '{user}' removed overwrite for user '{member}' in TextChannel '{channel}'", user = guard.session.subject, member = member, channel = channel);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Resubscribe the connections of the users affected by a changed overwrite to the channel.
+/// Role overwrites may affect every member of the space, member overwrites only `member`
+async fn sync_overwrite_subscriptions(
+    textchannel: &TextChannel,
+    member: Option<&Uuid>,
+    state: &AzumaState,
+) -> Result<(), AzumaError> {
+    // direct messages don't belong to a space and ignore overwrites
+    let space = match &textchannel.space {
+        Some(space) => Space::get_by_id(space, &state.db).await?,
+        None => return Ok(()),
+    };
+    let members: Vec<Uuid> = match member {
+        Some(member) => vec![*member],
+        None => space
+            .get_members(&state.db)
+            .await?
+            .into_iter()
+            .map(|member| member.member)
+            .collect(),
+    };
+    Space::sync_subscriptions(
+        &space.id,
+        &members,
+        Some(&textchannel.id),
+        &state.broker,
+        &state.db,
+    )
+    .await
+}
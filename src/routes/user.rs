@@ -4,14 +4,18 @@ use actix_web::web::{self};
 use actix_web::{HttpRequest, HttpResponse};
 use chrono::{DateTime, Utc};
 use log::info;
-use serde::{Deserialize, Serialize};
-use sodiumoxide::crypto::pwhash::argon2id13::{self, HashedPassword};
+use serde::{Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
-use crate::models::error::{Argon2idError, AzumaError};
+use crate::models::ban::Ban;
+use crate::models::error::AzumaError;
+use crate::models::invitecode::InviteCode;
 use crate::models::session::Session;
-use crate::models::stateactor::{GetOnlineStatus, OnlineStatus};
-use crate::models::user::User;
+use crate::models::space::Space;
+use crate::models::stateactor::{
+    DisconnectSessions, GetOnlineStatus, OnlineStatus, SetCustomStatus,
+};
+use crate::models::user::{CustomStatus, User};
 use crate::AzumaState;
 
 #[doc(hidden)]
@@ -19,6 +23,8 @@ use crate::AzumaState;
 pub struct RegisterUserRequest {
     name: String,
     password: String,
+    /// Required if the instance only allows registering with an invite code
+    invite: Option<String>,
 }
 
 #[doc(hidden)]
@@ -30,9 +36,40 @@ pub struct RegisterUserResponse {
 pub async fn register_user(
     data: web::Data<AzumaState>,
     request: web::Json<RegisterUserRequest>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AzumaError> {
-    let user = User::new(&request.name, &request.password, &data.db).await?;
-    let session = Session::new(&user, &data.db).await?;
+    // claiming the code, creating the user and granting what the code is for either happen all together or not at all
+    let mut tx = data.db.begin().await?;
+    // the first user can always register, otherwise nobody could create the first invite code
+    if request.invite.is_none()
+        && data.config.require_invite
+        && !User::is_first_registration(&mut tx).await?
+    {
+        return Err(AzumaError::Forbidden);
+    }
+    let user = User::new(&request.name, &request.password, &mut tx).await?;
+    let mut granted = None;
+    if let Some(code) = &request.invite {
+        let invite_code = InviteCode::claim(code, &mut tx).await?;
+        let joined = invite_code.grant(&user.id, &mut tx).await?;
+        granted = Some((invite_code, joined));
+    }
+    let joined_default_space = Space::insert_member(&Space::DEFAULT, &user.id, &mut tx).await?;
+    tx.commit().await?;
+
+    if let Some((invite_code, joined)) = &granted {
+        invite_code
+            .announce_grant(&user.id, *joined, &data.broker, &data.db)
+            .await?;
+    }
+    if joined_default_space {
+        let default_space = Space::get_by_id(&Space::DEFAULT, &data.db).await?;
+        default_space
+            .announce_member(&user.id, &data.broker, &data.db)
+            .await?;
+    }
+    let session =
+        Session::from_login(&user, &req, &data.config.session_lifetime(), &data.db).await?;
 
     let response_body = RegisterUserResponse {
         token: session.token,
@@ -53,25 +90,43 @@ pub struct LoginUserResponse {
     token: Uuid,
 }
 
-/// Try to login a user and respond with a valid session token
+#[doc(hidden)]
+#[derive(Serialize)]
+pub struct LoginBannedResponse {
+    message: String,
+    reason: String,
+    /// `None` if the ban is permanent
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Try to login a user and respond with a valid session token.
+/// Banned users are told the reason and expiry of their ban, but only after their password was verified
 pub async fn login_user(
     data: web::Data<AzumaState>,
     request: web::Json<LoginUserRequest>,
     req: HttpRequest,
 ) -> Result<HttpResponse, AzumaError> {
     let user = User::get_by_name(&request.name, &data.db).await?;
-    let hashed_password = HashedPassword::from_slice(&user.password).ok_or(Argon2idError)?;
 
-    if argon2id13::pwhash_verify(&hashed_password, request.password.as_bytes()) {
-        let session = Session::new(&user, &data.db).await?;
-        info!(target: "Access Control", "User '{}' logged in from '{}'", session.subject, req.connection_info().realip_remote_addr().unwrap_or("None"));
-        let response_body = LoginUserResponse {
-            token: session.token,
+    if !user.verify_password(&request.password)? || user.is_disabled() {
+        return Err(AzumaError::Forbidden);
+    }
+    if let Some(ban) = Ban::get_active(&user.id, &data.db).await? {
+        let response_body = LoginBannedResponse {
+            message: AzumaError::Forbidden.to_string(),
+            reason: ban.reason,
+            expires_at: ban.expires_at,
         };
-        Ok(HttpResponse::Ok().json(response_body))
-    } else {
-        Err(AzumaError::Forbidden)
+        return Ok(HttpResponse::Forbidden().json(response_body));
     }
+
+    let session =
+        Session::from_login(&user, &req, &data.config.session_lifetime(), &data.db).await?;
+    info!(target: "Access Control", "User '{}' logged in from '{}'", session.subject, req.connection_info().realip_remote_addr().unwrap_or("None"));
+    let response_body = LoginUserResponse {
+        token: session.token,
+    };
+    Ok(HttpResponse::Ok().json(response_body))
 }
 
 #[doc(hidden)]
@@ -79,6 +134,20 @@ pub async fn login_user(
 pub struct UpdateUserRequest {
     name: Option<String>,
     password: Option<String>,
+    /// Required to change the password
+    current_password: Option<String>,
+    /// `null` clears the custom status, leaving the field out keeps it
+    #[serde(default, deserialize_with = "deserialize_present")]
+    custom_status: Option<Option<CustomStatus>>,
+}
+
+/// Deserialize a field which is present in the request, so `null` can be told apart from a missing field
+fn deserialize_present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[doc(hidden)]
@@ -87,23 +156,74 @@ pub struct UpdateUserResponse {
     id: Uuid,
     name: String,
     created_at: DateTime<Utc>,
+    custom_status: Option<CustomStatus>,
+    /// A new session token, only set if the password was changed because that revokes all sessions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<Uuid>,
 }
 
-/// Update a users details
+/// Update a users details.
+/// Changing the password requires the current password and revokes all sessions of the user, the client gets a new token instead
 pub async fn update_user(
     data: web::Data<AzumaState>,
     request: web::Json<UpdateUserRequest>,
     session: Session,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AzumaError> {
+    if let Some(Some(custom_status)) = &request.custom_status {
+        custom_status.validate()?;
+    }
+
     let mut user = User::get_by_id(&session.subject, &data.db).await?;
+    if request.password.is_some() {
+        let current_password = request
+            .current_password
+            .as_deref()
+            .ok_or(AzumaError::BadRequest)?;
+        if !user.verify_password(current_password)? {
+            return Err(AzumaError::Forbidden);
+        }
+    }
     user.update(
         request.name.as_deref(),
         request.password.as_deref(),
         &data.db,
     )
     .await?;
+    if let Some(custom_status) = &request.custom_status {
+        user.set_custom_status(custom_status.as_ref(), &data.db)
+            .await?;
+        let spaces = Space::get_by_member(&user.id, &data.db)
+            .await?
+            .into_iter()
+            .map(|space| space.id)
+            .collect();
+        data.state.do_send(SetCustomStatus {
+            user: user.id,
+            custom_status: custom_status.clone(),
+            spaces,
+        });
+    }
+    let token = match request.password {
+        Some(_) => {
+            let tokens = Session::revoke_all(&user.id, None, &data.db).await?;
+            data.state.do_send(DisconnectSessions {
+                user: user.id,
+                tokens,
+            });
+            info!(target: "Access Control", "Revoked all sessions of user '{}' after a password change", user.id);
+            Some(
+                Session::from_login(&user, &req, &data.config.session_lifetime(), &data.db)
+                    .await?
+                    .token,
+            )
+        }
+        None => None,
+    };
     info!(target: "Access Control", "User '{}' was updated", user.id);
     let response_body = UpdateUserResponse {
+        custom_status: user.custom_status(),
+        token,
         id: user.id,
         name: user.name,
         created_at: user.created_at,
@@ -111,14 +231,10 @@ pub async fn update_user(
     Ok(HttpResponse::Ok().json(response_body))
 }
 
-#[derive(Deserialize)]
-pub struct FetchUserRequest {
-    pub user: Uuid,
-}
-
 #[derive(Serialize)]
 pub struct FetchUserResponse {
     pub onlinestatus: OnlineStatus,
+    pub custom_status: Option<CustomStatus>,
     // apply flatten in order to pull the fields out of the user struct
     #[serde(flatten)]
     pub user: User,
@@ -141,10 +257,15 @@ pub async fn fetch_user(
     };
 
     let user = User::get_by_id(&user_id, &state.db).await?;
-    let onlinestatus = state.state.send(GetOnlineStatus { user: user_id }).await?;
+    let onlinestatus = state
+        .state
+        .send(GetOnlineStatus { user: user_id })
+        .await?
+        .as_seen_by(&user_id, &session.subject);
     let response = FetchUserResponse {
-        onlinestatus: onlinestatus,
-        user: user,
+        onlinestatus,
+        custom_status: user.custom_status(),
+        user,
     };
 
     Ok(HttpResponse::Ok().json(response))
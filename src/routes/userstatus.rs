@@ -21,6 +21,7 @@ pub async fn set_onlinestatus(
         .state
         .send(SetOnlineStatus {
             user: session.subject,
+            connection_id: None,
             status: request.status,
         })
         .await?
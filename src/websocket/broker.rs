@@ -1,19 +1,39 @@
+use std::collections::{HashMap, HashSet};
+
 use actix::{Actor, Addr, Context, Handler, Message};
 use uuid::Uuid;
 
+use crate::models::directchannel::DirectChannel;
 use crate::models::message::ChatMessage;
 use crate::models::pub_sub::PubSub;
 use crate::models::session::Session;
+use crate::models::space::Space;
+use crate::models::stateactor::OnlineStatus;
+use crate::models::textchannel::TextChannel;
+use crate::models::user::CustomStatus;
 use crate::websocket::connection::Ws;
 
+/// Every authenticated connection is subscribed to this topic, it's used for events which aren't bound to a single channel
+pub const DEFAULT_TOPIC: Uuid = Uuid::nil();
+
 pub struct Broker {
     channel_subs: PubSub<Addr<Ws>, Uuid>,
+    /// Events concerning a whole space are sent to the connections of its members
+    space_subs: PubSub<Addr<Ws>, Uuid>,
+    /// The connections of every authenticated user, so connections can be subscribed to channels on behalf of their user
+    user_subs: PubSub<Addr<Ws>, Uuid>,
+    /// The spaces of every user with a connection, their presence updates are only sent to the members of these spaces.
+    /// Kept until the update telling that the user went offline was sent
+    user_spaces: HashMap<Uuid, HashSet<Uuid>>,
 }
 
 impl Broker {
     pub fn new() -> Self {
         Broker {
             channel_subs: PubSub::new(),
+            space_subs: PubSub::new(),
+            user_subs: PubSub::new(),
+            user_spaces: HashMap::new(),
         }
     }
 }
@@ -24,11 +44,12 @@ impl Actor for Broker {
 
 #[derive(Message)]
 #[rtype(result = "")]
-/// Used to sub to multiple textchannels at once.<br>
-/// **Note:** This is a temporary solution and not intended to be permanent
+/// Used to sub to the spaces of the user and all textchannels visible to a connection when it authenticates.<br>
+/// Channels created later are subscribed to with [`SubChannel`], direct messages by the broker itself
 pub struct MassSubChannel {
     pub addr: Addr<Ws>,
     pub session: Session,
+    pub spaces: Vec<Uuid>,
     pub topics: Vec<Uuid>,
 }
 
@@ -36,14 +57,52 @@ impl Handler<MassSubChannel> for Broker {
     type Result = ();
 
     fn handle(&mut self, msg: MassSubChannel, _ctx: &mut Self::Context) {
-        // Null UUID is used as a default channel
-        self.channel_subs.sub(&msg.addr, &Uuid::from_bytes([0; 16]));
+        self.channel_subs.sub(&msg.addr, &DEFAULT_TOPIC);
+        self.user_subs.sub(&msg.addr, &msg.session.subject);
+        self.user_spaces
+            .entry(msg.session.subject)
+            .or_default()
+            .extend(msg.spaces.iter().copied());
+        for space in msg.spaces {
+            self.space_subs.sub(&msg.addr, &space);
+        }
         for channel in msg.topics {
             self.channel_subs.sub(&msg.addr, &channel)
         }
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Subscribe a connection to the events of a single channel
+pub struct SubChannel {
+    pub addr: Addr<Ws>,
+    pub channel: Uuid,
+}
+
+impl Handler<SubChannel> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubChannel, _ctx: &mut Self::Context) {
+        self.channel_subs.sub(&msg.addr, &msg.channel);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UnsubChannel {
+    pub addr: Addr<Ws>,
+    pub channel: Uuid,
+}
+
+impl Handler<UnsubChannel> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: UnsubChannel, _ctx: &mut Self::Context) {
+        self.channel_subs.unsub(&msg.addr, &msg.channel);
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "")]
 pub struct UnsubAll {
@@ -55,25 +114,251 @@ impl Handler<UnsubAll> for Broker {
 
     fn handle(&mut self, msg: UnsubAll, _ctx: &mut Self::Context) {
         self.channel_subs.unsub_all(&msg.addr);
+        self.space_subs.unsub_all(&msg.addr);
+        self.user_subs.unsub_all(&msg.addr);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Subscribe all connections of a user to a space and the given channels of it, used when the user joins the space
+pub struct SubSpace {
+    pub user: Uuid,
+    pub space: Uuid,
+    pub channels: Vec<Uuid>,
+}
+
+impl Handler<SubSpace> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubSpace, _ctx: &mut Self::Context) {
+        if let Some(spaces) = self.user_spaces.get_mut(&msg.user) {
+            spaces.insert(msg.space);
+        }
+        let connections: Vec<Addr<Ws>> = self
+            .user_subs
+            .get_subs(&msg.user)
+            .into_iter()
+            .cloned()
+            .collect();
+        for addr in connections {
+            self.space_subs.sub(&addr, &msg.space);
+            for channel in &msg.channels {
+                self.channel_subs.sub(&addr, channel);
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Unsubscribe all connections of a user from a space and the given channels of it, used when the user leaves the space
+pub struct UnsubSpace {
+    pub user: Uuid,
+    pub space: Uuid,
+    pub channels: Vec<Uuid>,
+}
+
+impl Handler<UnsubSpace> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: UnsubSpace, _ctx: &mut Self::Context) {
+        if let Some(spaces) = self.user_spaces.get_mut(&msg.user) {
+            spaces.remove(&msg.space);
+        }
+        let connections: Vec<Addr<Ws>> = self
+            .user_subs
+            .get_subs(&msg.user)
+            .into_iter()
+            .cloned()
+            .collect();
+        for addr in connections {
+            self.space_subs.unsub(&addr, &msg.space);
+            for channel in &msg.channels {
+                self.channel_subs.unsub(&addr, channel);
+            }
+        }
     }
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
+/// Subscribe all connections of a user to the `visible` channels and unsubscribe them from the `hidden` ones, used when the permissions of the user changed
+pub struct SyncChannels {
+    pub user: Uuid,
+    pub visible: Vec<Uuid>,
+    pub hidden: Vec<Uuid>,
+}
+
+impl Handler<SyncChannels> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: SyncChannels, _ctx: &mut Self::Context) {
+        let connections: Vec<Addr<Ws>> = self
+            .user_subs
+            .get_subs(&msg.user)
+            .into_iter()
+            .cloned()
+            .collect();
+        for addr in connections {
+            for channel in &msg.visible {
+                self.channel_subs.sub(&addr, channel);
+            }
+            for channel in &msg.hidden {
+                self.channel_subs.unsub(&addr, channel);
+            }
+        }
+    }
+}
+
+/// Events which are forwarded to every [`Ws`] subscribed to the affected topic
+#[derive(Clone, Message)]
+#[rtype(result = "()")]
 pub enum Broadcast {
     ChatMessage(ChatMessage),
+    MessageUpdated(ChatMessage),
+    MessageDeleted {
+        id: Uuid,
+        channel: Uuid,
+    },
+    /// Nobody is subscribed to a new channel yet, so this is sent to the members of its space
+    ChannelCreated(TextChannel),
+    ChannelUpdated(TextChannel),
+    ChannelDeleted {
+        id: Uuid,
+    },
+    /// The connections of the members are subscribed to the new channel before this is sent to its subscribers,
+    /// so nobody else ever learns about the channel
+    DirectChannelCreated(DirectChannel),
+    SpaceUpdated(Space),
+    /// The channels of the space are deleted along with it
+    SpaceDeleted {
+        id: Uuid,
+        channels: Vec<Uuid>,
+    },
+    SpaceMemberJoined {
+        space: Uuid,
+        user: Uuid,
+    },
+    SpaceMemberLeft {
+        space: Uuid,
+        user: Uuid,
+    },
+    /// Sent to the connections of the members of the spaces the user is in.
+    /// The real status is sent, each [`Ws`] hides it from other users if necessary
+    PresenceUpdate {
+        user: Uuid,
+        status: OnlineStatus,
+        custom_status: Option<CustomStatus>,
+        /// The spaces of a user without connections, the broker only knows the spaces of connected users
+        spaces: Option<Vec<Uuid>>,
+    },
+}
+
+/// Who receives a [`Broadcast`]
+enum Topic {
+    /// The subscribers of a channel or the [`DEFAULT_TOPIC`]
+    Channel(Uuid),
+    /// The connections of the members of a space
+    Space(Uuid),
+    /// The connections of the user and of everyone sharing a space with him/her
+    SharedSpaces {
+        user: Uuid,
+        spaces: Option<Vec<Uuid>>,
+    },
+}
+
+impl Broadcast {
+    /// The topic whose subscribers receive this event
+    fn topic(&self) -> Topic {
+        match self {
+            Broadcast::ChatMessage(m) | Broadcast::MessageUpdated(m) => Topic::Channel(m.channel),
+            Broadcast::MessageDeleted { channel, .. } => Topic::Channel(*channel),
+            Broadcast::ChannelCreated(channel) => match channel.space {
+                Some(space) => Topic::Space(space),
+                None => Topic::Channel(channel.id),
+            },
+            Broadcast::ChannelUpdated(channel) => Topic::Channel(channel.id),
+            Broadcast::ChannelDeleted { id } => Topic::Channel(*id),
+            Broadcast::DirectChannelCreated(direct_channel) => {
+                Topic::Channel(direct_channel.channel.id)
+            }
+            Broadcast::SpaceUpdated(space) => Topic::Space(space.id),
+            Broadcast::SpaceDeleted { id, .. } => Topic::Space(*id),
+            Broadcast::SpaceMemberJoined { space, .. }
+            | Broadcast::SpaceMemberLeft { space, .. } => Topic::Space(*space),
+            Broadcast::PresenceUpdate { user, spaces, .. } => Topic::SharedSpaces {
+                user: *user,
+                spaces: spaces.clone(),
+            },
+        }
+    }
 }
 
 impl Handler<Broadcast> for Broker {
     type Result = ();
 
     fn handle(&mut self, msg: Broadcast, _ctx: &mut Self::Context) {
+        if let Broadcast::DirectChannelCreated(direct_channel) = &msg {
+            for member in &direct_channel.members {
+                let connections: Vec<Addr<Ws>> = self
+                    .user_subs
+                    .get_subs(member)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                for addr in connections {
+                    self.channel_subs.sub(&addr, &direct_channel.channel.id);
+                }
+            }
+        }
+
+        let subs = match msg.topic() {
+            Topic::Channel(channel) => self.channel_subs.get_subs(&channel),
+            Topic::Space(space) => self.space_subs.get_subs(&space),
+            Topic::SharedSpaces { user, spaces } => {
+                let spaces = spaces.unwrap_or_else(|| {
+                    self.user_spaces
+                        .get(&user)
+                        .map(|spaces| spaces.iter().copied().collect())
+                        .unwrap_or_default()
+                });
+                // a connection would get the update once for every shared space otherwise.
+                // `Addr` is hashed by the identity of its actor, which never changes
+                #[allow(clippy::mutable_key_type)]
+                let subs: HashSet<&Addr<Ws>> = spaces
+                    .iter()
+                    .flat_map(|space| self.space_subs.get_subs(space))
+                    .chain(self.user_subs.get_subs(&user))
+                    .collect();
+                subs.into_iter().collect()
+            }
+        };
+        for sub in subs {
+            sub.do_send(msg.clone());
+        }
+
+        // Subscriptions to new channels are added by the connections themselves once they checked their permissions,
+        // subscriptions to deleted channels are dropped here after the subscribers were notified
         match msg {
-            Broadcast::ChatMessage(m) => {
-                for sub in self.channel_subs.get_subs(&m.channel) {
-                    sub.do_send(m.clone());
+            Broadcast::ChannelDeleted { id } => self.channel_subs.remove_topic(&id),
+            Broadcast::SpaceDeleted { id, channels } => {
+                self.space_subs.remove_topic(&id);
+                for spaces in self.user_spaces.values_mut() {
+                    spaces.remove(&id);
                 }
+                for channel in channels {
+                    self.channel_subs.remove_topic(&channel);
+                }
+            }
+            Broadcast::PresenceUpdate {
+                user,
+                status: OnlineStatus::Offline,
+                ..
+            } if self.user_subs.get_subs(&user).is_empty() => {
+                self.user_spaces.remove(&user);
             }
+            _ => {}
         }
     }
 }
@@ -1,19 +1,32 @@
+use std::time::{Duration, Instant};
+
 use actix::{
-    Actor, ActorContext, ActorFutureExt, AsyncContext, ContextFutureSpawner, Handler,
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, Handler,
     Message as MessageMacro, StreamHandler, WrapFuture,
 };
 use actix_web::web;
-use actix_web_actors::ws::{self, Message};
+use actix_web_actors::ws::{self, CloseCode, CloseReason, Message};
 use log::info;
 use uuid::Uuid;
 
+use crate::models::directchannel::DirectChannel;
 use crate::models::error::AzumaError;
 use crate::models::message::ChatMessage;
+use crate::models::permission::{Permissions, UserPermissions};
 use crate::models::session::Session;
-use crate::models::stateactor::{AddUserSession, RemoveUserSession};
+use crate::models::space::Space;
+use crate::models::stateactor::{
+    AddUserSession, OnlineStatus, RemoveUserSession, ReportActivity, SetOnlineStatus,
+};
 use crate::models::textchannel::TextChannel;
-use crate::models::ws::{AwspRequestMessage, AwspResponseMessage};
-use crate::websocket::broker::{MassSubChannel, UnsubAll};
+use crate::models::user::{CustomStatus, User};
+use crate::models::ws::{
+    AwspRequest, AwspRequestMessage, AwspResponse, AwspResponseMessage,
+    CLOSE_AUTHENTICATION_TIMEOUT,
+};
+use crate::websocket::broker::{
+    Broadcast, MassSubChannel, SubChannel, UnsubAll, UnsubChannel, DEFAULT_TOPIC,
+};
 use crate::AzumaState;
 
 pub struct Ws {
@@ -21,33 +34,119 @@ pub struct Ws {
     pub subject: Option<Uuid>,
     /// Each connection has its own id to seperate it from the other sessions a user could have
     pub connection_id: Uuid,
+    /// The last time the client sent any frame, used to detect dead connections
+    pub last_heartbeat: Instant,
+    /// Set as soon as an `Authenticate` request is being handled and only reset if it fails, so a connection can't authenticate twice
+    authenticating: bool,
+}
+
+impl Ws {
+    pub fn new(data: web::Data<AzumaState>) -> Self {
+        Ws {
+            data,
+            subject: None,
+            connection_id: Uuid::new_v4(),
+            last_heartbeat: Instant::now(),
+            authenticating: false,
+        }
+    }
+
+    /// Close the connection if the client didn't authenticate within the configured time
+    fn start_auth_timeout(&self, ctx: &mut <Self as Actor>::Context) {
+        let timeout = Duration::from_secs(self.data.config.auth_timeout);
+        ctx.run_later(timeout, |actor, ctx| {
+            if actor.subject.is_none() {
+                info!(target: "Websocket", "Closing unauthenticated websocket connection '{}'", actor.connection_id);
+                ctx.close(Some(CloseReason {
+                    code: CloseCode::Other(CLOSE_AUTHENTICATION_TIMEOUT),
+                    description: Some("authentication timeout".to_string()),
+                }));
+                ctx.stop();
+            }
+        });
+    }
+
+    /// Ping the client regularly and stop the actor if the client didn't send anything for longer than the configured timeout
+    fn start_heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
+        let interval = Duration::from_secs(self.data.config.heartbeat_interval);
+        let timeout = Duration::from_secs(self.data.config.client_timeout);
+        ctx.run_interval(interval, move |actor, ctx| {
+            if actor.last_heartbeat.elapsed() > timeout {
+                info!(target: "Websocket", "Closing timed out websocket connection '{}'", actor.connection_id);
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    /// Register the connection for its user once it authenticated. This runs on the actor itself, so it only happens
+    /// while the connection is still open and [`stopped`](Actor::stopped) always sees the subject of a registered connection
+    fn register(&mut self, authentication: Authentication, ctx: &mut <Self as Actor>::Context) {
+        let Authentication {
+            session,
+            spaces,
+            topics,
+            custom_status,
+        } = authentication;
+        self.subject = Some(session.subject);
+        self.data.broker.do_send(MassSubChannel {
+            addr: ctx.address(),
+            session: session.clone(),
+            spaces,
+            topics,
+        });
+        self.data.state.do_send(AddUserSession {
+            subject: session.subject,
+            addr: ctx.address(),
+            connection_id: self.connection_id,
+            token: session.token,
+            custom_status,
+        });
+    }
 }
 
+/// Close the connection with one of the close codes defined in [`crate::models::ws`]
 #[derive(MessageMacro)]
 #[rtype(result = "()")]
-struct SetSubject(Option<Uuid>);
+pub struct Disconnect {
+    pub code: u16,
+    pub reason: &'static str,
+}
 
 impl Actor for Ws {
     type Context = ws::WebsocketContext<Self>;
 
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_heartbeat(ctx);
+        self.start_auth_timeout(ctx);
+        let res = AwspResponse::event(AwspResponseMessage::Hello {
+            heartbeat_interval: self.data.config.heartbeat_interval,
+            client_timeout: self.data.config.client_timeout,
+        });
+        ctx.text(serde_json::to_string(&res).expect("couldn't serialize AwspResponseMessage"));
+    }
+
     fn stopped(&mut self, ctx: &mut Self::Context) {
         self.data.broker.do_send(UnsubAll {
             addr: ctx.address(),
         });
-        match self.subject {
-            Some(uuid) => {
-                self.data.state.do_send(RemoveUserSession {
-                    subject: uuid,
-                    connection_id: self.connection_id,
-                });
-            }
-            None => (),
-        };
+        if let Some(uuid) = self.subject {
+            self.data.state.do_send(RemoveUserSession {
+                subject: uuid,
+                connection_id: self.connection_id,
+            });
+        }
     }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Ws {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        // any frame proves that the client is still alive, not only pongs
+        if msg.is_ok() {
+            self.last_heartbeat = Instant::now();
+        }
+
         match msg {
             Ok(Message::Ping(msg)) => ctx.pong(&msg),
             Ok(Message::Close(_)) => {
@@ -55,51 +154,53 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Ws {
                 ctx.stop();
             }
             Ok(Message::Text(text)) => {
-                let data = self.data.clone();
-                let addr = ctx.address();
-                let connection_id = self.connection_id;
+                let (id, mut msg) = match serde_json::from_str::<AwspRequest>(&text) {
+                    Ok(req) => (req.id, Ok(req.message)),
+                    Err(_) => (AwspRequest::recover_id(&text), Err(AzumaError::BadRequest)),
+                };
+
+                // this has to be checked before handling the request asynchronously, otherwise two `Authenticate` requests sent
+                // right after each other would both be handled
+                let mut starts_authentication = false;
+                if let Ok(AwspRequestMessage::Authenticate { .. }) = msg {
+                    if self.authenticating {
+                        msg = Err(AzumaError::BadRequest);
+                    } else {
+                        self.authenticating = true;
+                        starts_authentication = true;
+                    }
+                }
+
+                let request = RequestContext {
+                    data: self.data.clone(),
+                    addr: ctx.address(),
+                    subject: self.subject,
+                    connection_id: self.connection_id,
+                };
                 async move {
-                    match serde_json::from_str::<AwspRequestMessage>(&text) {
-                        Ok(AwspRequestMessage::Authenticate { token }) => {
-                            let session = match Session::get_and_renew(&token, &data.db).await {
-                                Ok(session) => session,
-                                Err(AzumaError::NotFound) => return Err(AzumaError::Unauthorized),
-                                Err(err) => return Err(err),
-                            };
-
-                            let channels = match TextChannel::get_all(&data.db).await {
-                                Ok(vec) => vec,
-                                Err(err) => return Err(err),
-                            };
-                            let mut topics = Vec::new();
-                            for channel in channels {
-                                topics.push(channel.id)
-                            }
-                            info!(target: "Websocket", "Authenticated websocket session of user '{}'", session.subject);
-                            data.broker
-                                .send(MassSubChannel {
-                                    addr: addr.clone(),
-                                    session: session.clone(),
-                                    topics,
-                                })
-                                .await?;
-                            data.state.do_send(AddUserSession { subject: session.subject, addr: addr.clone(), connection_id });
-                            addr.do_send(SetSubject(Some(session.subject)));
-
-                            let res = AwspResponseMessage::Welcome;
-                            Ok(res)
-                        }
-                        Err(_) => Err(AzumaError::BadRequest),
+                    match msg {
+                        Ok(msg) => request.handle(msg).await,
+                        Err(err) => Err(err),
                     }
                 }
                 .into_actor(self)
-                .map(|result, _actor, ctx| {
+                .map(move |result, actor, ctx| {
+                    if starts_authentication && result.is_err() {
+                        actor.authenticating = false;
+                    }
+
                     let res = match result {
-                        Ok(res) => res,
+                        Ok((res, authentication)) => {
+                            if let Some(authentication) = authentication {
+                                actor.register(authentication, ctx);
+                            }
+                            res
+                        }
                         Err(err) => AwspResponseMessage::Error {
                             message: format!("{}", err),
                         },
                     };
+                    let res = AwspResponse::reply(id, res);
 
                     ctx.text(
                         serde_json::to_string(&res)
@@ -113,18 +214,286 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Ws {
     }
 }
 
-impl Handler<ChatMessage> for Ws {
+/// Everything needed to handle an [`AwspRequestMessage`], taken from the [`Ws`] actor so requests can be handled asynchronously
+struct RequestContext {
+    data: web::Data<AzumaState>,
+    addr: Addr<Ws>,
+    subject: Option<Uuid>,
+    connection_id: Uuid,
+}
+
+/// What a connection subscribes to after a successful [`AwspRequestMessage::Authenticate`], applied by [`Ws::register`]
+struct Authentication {
+    session: Session,
+    spaces: Vec<Uuid>,
+    topics: Vec<Uuid>,
+    custom_status: Option<CustomStatus>,
+}
+
+impl RequestContext {
+    /// Handle a request, an `Authenticate` request additionally results in the [`Authentication`] of the connection
+    async fn handle(
+        self,
+        msg: AwspRequestMessage,
+    ) -> Result<(AwspResponseMessage, Option<Authentication>), AzumaError> {
+        let res = match msg {
+            AwspRequestMessage::Authenticate { token } => {
+                let authentication = self.authenticate(&token).await?;
+                return Ok((AwspResponseMessage::Welcome, Some(authentication)));
+            }
+            AwspRequestMessage::Subscribe { channel } => self.subscribe(&channel).await,
+            AwspRequestMessage::Unsubscribe { channel } => self.unsubscribe(&channel).await,
+            AwspRequestMessage::SendMessage {
+                channel,
+                content,
+                nonce,
+            } => self.send_message(&channel, &content, nonce).await,
+            AwspRequestMessage::SetStatus { status } => self.set_status(status).await,
+            AwspRequestMessage::Activity => self.report_activity(true).await,
+            AwspRequestMessage::Idle => self.report_activity(false).await,
+        }?;
+        Ok((res, None))
+    }
+
+    /// Every request except [`AwspRequestMessage::Authenticate`] needs an authenticated connection
+    fn subject(&self) -> Result<Uuid, AzumaError> {
+        self.subject.ok_or(AzumaError::Unauthorized)
+    }
+
+    async fn authenticate(self, token: &Uuid) -> Result<Authentication, AzumaError> {
+        let session = match Session::get_and_renew(
+            token,
+            &self.data.config.session_lifetime(),
+            &self.data.db,
+        )
+        .await
+        {
+            Ok(session) => session,
+            Err(AzumaError::NotFound) => return Err(AzumaError::Unauthorized),
+            Err(err) => return Err(err),
+        };
+
+        let user = User::get_by_id(&session.subject, &self.data.db).await?;
+        let spaces: Vec<Uuid> = Space::get_by_member(&session.subject, &self.data.db)
+            .await?
+            .into_iter()
+            .map(|space| space.id)
+            .collect();
+        let mut channels = Vec::new();
+        for space in &spaces {
+            channels.extend(TextChannel::get_all(&self.data.db, space).await?);
+        }
+        let permissions = UserPermissions::load(&session.subject, &self.data.db).await?;
+        let direct_channels = DirectChannel::get_by_member(&session.subject, &self.data.db).await?;
+        let topics = channels
+            .into_iter()
+            .map(|channel| channel.id)
+            .filter(|channel| {
+                permissions
+                    .in_channel(channel)
+                    .contains(Permissions::VIEW_CHANNEL)
+            })
+            .chain(
+                direct_channels
+                    .into_iter()
+                    .map(|direct_channel| direct_channel.channel.id),
+            )
+            .collect();
+        info!(target: "Websocket", "Authenticated websocket session of user '{}'", session.subject);
+
+        Ok(Authentication {
+            session,
+            spaces,
+            topics,
+            custom_status: user.custom_status(),
+        })
+    }
+
+    async fn subscribe(self, channel: &Uuid) -> Result<AwspResponseMessage, AzumaError> {
+        let subject = self.subject()?;
+        TextChannel::get_by_id(&self.data.db, channel).await?;
+        let permissions =
+            UserPermissions::load_for_channel(&subject, channel, &self.data.db).await?;
+        if !permissions
+            .in_channel(channel)
+            .contains(Permissions::VIEW_CHANNEL)
+        {
+            return Err(AzumaError::Forbidden);
+        }
+
+        self.data
+            .broker
+            .send(SubChannel {
+                addr: self.addr.clone(),
+                channel: *channel,
+            })
+            .await?;
+        Ok(AwspResponseMessage::Subscribed { channel: *channel })
+    }
+
+    async fn unsubscribe(self, channel: &Uuid) -> Result<AwspResponseMessage, AzumaError> {
+        self.subject()?;
+        // the default topic isn't a channel, leaving it would mean missing events concerning the whole connection
+        if channel == &DEFAULT_TOPIC {
+            return Err(AzumaError::BadRequest);
+        }
+
+        self.data
+            .broker
+            .send(UnsubChannel {
+                addr: self.addr.clone(),
+                channel: *channel,
+            })
+            .await?;
+        Ok(AwspResponseMessage::Unsubscribed { channel: *channel })
+    }
+
+    async fn send_message(
+        self,
+        channel: &Uuid,
+        content: &str,
+        nonce: Option<String>,
+    ) -> Result<AwspResponseMessage, AzumaError> {
+        let subject = self.subject()?;
+        TextChannel::get_by_id(&self.data.db, channel).await?;
+        let permissions =
+            UserPermissions::load_for_channel(&subject, channel, &self.data.db).await?;
+        if !permissions
+            .in_channel(channel)
+            .contains(Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES)
+        {
+            return Err(AzumaError::Forbidden);
+        }
+
+        info!(target: "Websocket", "ChatMessage sent in '{channel}' by '{user}'", channel = channel, user = subject);
+        let chat_message =
+            ChatMessage::new(&subject, channel, content, &self.data.broker, &self.data.db).await?;
+        Ok(AwspResponseMessage::MessageSent {
+            id: chat_message.id,
+            nonce,
+        })
+    }
+
+    async fn set_status(self, status: OnlineStatus) -> Result<AwspResponseMessage, AzumaError> {
+        let subject = self.subject()?;
+        self.data
+            .state
+            .send(SetOnlineStatus {
+                user: subject,
+                connection_id: Some(self.connection_id),
+                status,
+            })
+            .await??;
+        Ok(AwspResponseMessage::StatusSet { status })
+    }
+
+    async fn report_activity(self, active: bool) -> Result<AwspResponseMessage, AzumaError> {
+        let subject = self.subject()?;
+        self.data
+            .state
+            .send(ReportActivity {
+                user: subject,
+                connection_id: self.connection_id,
+                active,
+            })
+            .await??;
+        Ok(AwspResponseMessage::ActivityReported)
+    }
+}
+
+impl Handler<Broadcast> for Ws {
     type Result = ();
 
-    fn handle(&mut self, msg: ChatMessage, ctx: &mut Self::Context) {
-        let res = AwspResponseMessage::Message(msg);
+    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
+        let res = match msg {
+            Broadcast::ChatMessage(m) => AwspResponseMessage::Message(m),
+            Broadcast::MessageUpdated(m) => AwspResponseMessage::MessageUpdated(m),
+            Broadcast::MessageDeleted { id, channel } => {
+                AwspResponseMessage::MessageDeleted { id, channel }
+            }
+            Broadcast::ChannelCreated(channel) => {
+                // new channels are announced to every member of the space, so we have to check whether this user may see it
+                let subject = match self.subject {
+                    Some(subject) => subject,
+                    None => return,
+                };
+                let data = self.data.clone();
+                let addr = ctx.address();
+                async move {
+                    let permissions =
+                        UserPermissions::load_for_channel(&subject, &channel.id, &data.db).await?;
+                    if !permissions
+                        .in_channel(&channel.id)
+                        .contains(Permissions::VIEW_CHANNEL)
+                    {
+                        return Ok(None);
+                    }
+
+                    data.broker
+                        .send(SubChannel {
+                            addr,
+                            channel: channel.id,
+                        })
+                        .await?;
+                    Ok::<_, AzumaError>(Some(channel))
+                }
+                .into_actor(self)
+                .map(|result, _actor, ctx| {
+                    if let Ok(Some(channel)) = result {
+                        let res = AwspResponse::event(AwspResponseMessage::ChannelCreated(channel));
+                        ctx.text(
+                            serde_json::to_string(&res)
+                                .expect("couldn't serialize AwspResponseMessage"),
+                        );
+                    }
+                })
+                .spawn(ctx);
+                return;
+            }
+            Broadcast::ChannelUpdated(channel) => AwspResponseMessage::ChannelUpdated(channel),
+            Broadcast::ChannelDeleted { id } => AwspResponseMessage::ChannelDeleted { id },
+            Broadcast::DirectChannelCreated(direct_channel) => {
+                AwspResponseMessage::DirectChannelCreated(direct_channel)
+            }
+            Broadcast::SpaceUpdated(space) => AwspResponseMessage::SpaceUpdated(space),
+            Broadcast::SpaceDeleted { id, .. } => AwspResponseMessage::SpaceDeleted { id },
+            Broadcast::SpaceMemberJoined { space, user } => {
+                AwspResponseMessage::SpaceMemberJoined { space, user }
+            }
+            Broadcast::SpaceMemberLeft { space, user } => {
+                AwspResponseMessage::SpaceMemberLeft { space, user }
+            }
+            Broadcast::PresenceUpdate {
+                user,
+                status,
+                custom_status,
+                ..
+            } => {
+                let viewer = match self.subject {
+                    Some(subject) => subject,
+                    None => return,
+                };
+                AwspResponseMessage::PresenceUpdate {
+                    user,
+                    status: status.as_seen_by(&user, &viewer),
+                    custom_status,
+                }
+            }
+        };
+        let res = AwspResponse::event(res);
         ctx.text(serde_json::to_string(&res).expect("couldn't serialize AwspResponseMessage"));
     }
 }
 
-impl Handler<SetSubject> for Ws {
+impl Handler<Disconnect> for Ws {
     type Result = ();
-    fn handle(&mut self, msg: SetSubject, _ctx: &mut Self::Context) {
-        self.subject = msg.0;
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) {
+        info!(target: "Websocket", "Closing websocket connection '{}': {}", self.connection_id, msg.reason);
+        ctx.close(Some(CloseReason {
+            code: CloseCode::Other(msg.code),
+            description: Some(msg.reason.to_string()),
+        }));
+        ctx.stop();
     }
 }